@@ -15,7 +15,7 @@ interface BaseRecord {
   /** The current domain-specific status of the record (e.g., 'scheduled', 'active'). */
   status: string; // Consider using specific string literal unions later (e.g., JobStatus)
 
-  /** Optimistic concurrency control version number. Incremented on each update. */
+  /** Integer optimistic concurrency control version. Incremented on each update. */
   version: number;
 
   /** The ID of the user who created this record. Optional. */
@@ -29,7 +29,7 @@ interface BaseRecord {
   /** ISO 8601 timestamp string of when the record was created. */
   created_at: string;
 
-  /** ISO 8601 timestamp string of when the record was last updated. */
+  /** ISO 8601 timestamp of the record's last change: the server's HLC stamp (see `ChangeLogRecord.hlc`), which `since` filters on. */
   updated_at: string;
 
   /** The primary object name for this record (e.g., 'job', 'customer'). */
@@ -124,9 +124,12 @@ interface TenantRecord {
   /** @sqlJson */
   data: TenantData;
 
-  /** Optimistic concurrency control version number. Incremented on each update. */
+  /** Integer optimistic concurrency control version. Incremented on each update. */
   version: number;
 
+  /** The tenant's schema version, starting at 1 and bumped by every change to its object metadata or layouts. Tags every change_log row and pull. */
+  schema_version: number;
+
   /** The ID of the user who created this record. Optional. */
   /** @reference UserRecord */
   created_by?: string;
@@ -138,7 +141,7 @@ interface TenantRecord {
   /** ISO 8601 timestamp string of when the record was created. */
   created_at: string;
 
-  /** ISO 8601 timestamp string of when the record was last updated. */
+  /** ISO 8601 timestamp of the record's last change: the server's HLC stamp (see `ChangeLogRecord.hlc`), which `since` filters on. */
   updated_at: string;
 }
 
@@ -753,7 +756,7 @@ interface ObjectMetadataRecord {
   /** @sqlJson */
   data: ObjectMetadataData;
 
-  /** Optimistic concurrency control version number. */
+  /** Integer optimistic concurrency control version. */
   version: number;
 
   /** The ID of the user who created this record. */
@@ -839,7 +842,7 @@ interface LayoutDefinitionRecord {
   /** @sqlJson */
   data: LayoutDefinitionData;
 
-  /** Optimistic concurrency control version number. */
+  /** Integer optimistic concurrency control version. */
   version: number;
 
   /** The ID of the user who created this record. */
@@ -891,7 +894,7 @@ interface ChangeLogRecord {
   /** @reference UserRecord */
   user_id: string;
 
-  /** The timestamp of when the change was recorded on the server. */
+  /** The timestamp the client reported for the change. Part of the hashed content; never used for ordering. */
   created_at: IsoTimestamp;
 
   /** The name of the table/object being changed (e.g., 'job', 'customer'). */
@@ -900,6 +903,9 @@ interface ChangeLogRecord {
   /** The ID of the specific record that was changed. */
   record_id: string;
 
+  /** What the change does to the record: 'create', 'update' (merge patch, the default) or 'delete' (soft delete via `status`). */
+  operation?: string;
+
   /** The JSON object representing the change itself (the "delta"). The format can vary (e.g., a JSON Patch, or a partial object). */
   /** @sqlJson */
   change_data: any;
@@ -909,6 +915,151 @@ interface ChangeLogRecord {
 
   /** The `state_hash` of the change log entry that immediately preceded this one. This creates the chain. */
   previous_state_hash: string;
+
+  /** How the content hash preimage was built: 1 = fields concatenated with serde_json `change_data` (legacy, the default), 2 = concatenated with RFC 8785 canonical `change_data`, 3 = one RFC 8785 canonical array of the fields including `operation`. */
+  hash_version?: number;
+
+  /** The server's hybrid logical clock stamp from when it accepted the change: its wall-clock milliseconds plus a counter, as an RFC 3339 timestamp with nine fractional digits. Never decreases within a tenant. Rows accepted before stamping are backfilled from `created_at` when the server migrates the database; it stays absent where `created_at` isn't RFC 3339. */
+  hlc?: IsoTimestamp;
+
+  /** The tenant's `schema_version` when the change was accepted; a client on an older version stops applying changes and upgrades. Rows from before versions were tracked are backfilled with 1. */
+  schema_version?: number;
+}
+
+/**
+ * A signed statement of a tenant's chain head, issued periodically by the server.
+ * @platform server
+ * @db only
+ */
+interface ChainCheckpointRecord {
+  /** Auto-incrementing identifier of the checkpoint. */
+  id: number;
+
+  /** The ID of the tenant whose chain is checkpointed. */
+  /** @reference TenantRecord */
+  tenant_id: string;
+
+  /** The `sequence_id` of the change_log row at the head when the checkpoint was signed. */
+  sequence_id: number;
+
+  /** The `state_hash` of that row. */
+  state_hash: string;
+
+  /** When the checkpoint was signed. */
+  timestamp: IsoTimestamp;
+
+  /** Hex-encoded Ed25519 public key of the signing server. */
+  public_key: string;
+
+  /** Hex-encoded Ed25519 signature over the RFC 8785 canonical JSON of `{ sequence_id, state_hash, tenant_id, timestamp }`. */
+  signature: string;
+}
+
+/**
+ * A change_log compaction anchor. Rows up to `sequence_id` were folded into the
+ * snapshot's entries and deleted; the tenant's chain now starts at `state_hash`.
+ * @platform server
+ * @db only
+ */
+interface ChangeLogSnapshotRecord {
+  /** Server-generated UUID of the snapshot, returned with `bootstrap_required` when a client's anchor was compacted away. */
+  id: string;
+
+  /** @reference TenantRecord */
+  tenant_id: string;
+
+  /** The `sequence_id` of the last compacted row. */
+  sequence_id: number;
+
+  /** The `state_hash` of the last compacted row; the new start of the tenant's chain. */
+  state_hash: string;
+
+  /** When the compaction ran. */
+  created_at: IsoTimestamp;
+
+  /** How many change_log rows the compaction removed. */
+  pruned_rows: number;
+}
+
+/**
+ * The state of one record as of a snapshot's anchor. Only the latest snapshot keeps its entries.
+ * @platform server
+ * @db only
+ */
+interface ChangeLogSnapshotEntryRecord {
+  /** @reference ChangeLogSnapshotRecord */
+  snapshot_id: string;
+
+  object_name: string;
+
+  record_id: string;
+
+  /** The record's `data` document. Tombstones keep the document they were deleted with. */
+  /** @sqlJson */
+  data: any;
+
+  /** 1 when the record was soft-deleted at the anchor. */
+  deleted: boolean;
+
+  /** The record's `version` at the anchor, so a projection rebuild can restore it. */
+  version: number;
+
+  /** @reference UserRecord */
+  created_by: string;
+
+  created_at: IsoTimestamp;
+
+  /** @reference UserRecord */
+  modified_by: string;
+
+  updated_at: IsoTimestamp;
+}
+
+/**
+ * A named replication channel of a tenant: which tables and rows its users receive from
+ * GET /sync, GET /sync/bootstrap, GET /sync/v2 and /ws. Once a tenant has a profile,
+ * only users assigned one may pull.
+ * @platform server
+ * @db only
+ */
+interface SecurityProfileRecord {
+  /** Server-generated UUID of the profile. */
+  id: string;
+
+  /** @reference TenantRecord */
+  tenant_id: string;
+
+  /** Unique within the tenant (e.g. "Admin", "Tech"). */
+  name: string;
+
+  /** `{ tables?: string[], filters?: {...} }`, in the format of GET /sync's `tables` and `filters`. Filter values of `"$user_id"` stand for the pulling user. */
+  /** @sqlJson */
+  definition: any;
+
+  created_at: IsoTimestamp;
+
+  updated_at: IsoTimestamp;
+}
+
+/**
+ * The security profile a user replicates through; at most one per user.
+ * @platform server
+ * @db only
+ */
+interface SecurityProfileAssignmentRecord {
+  /**
+   * @reference UserRecord
+   * @unique
+   */
+  user_id: string;
+
+  /** @reference TenantRecord */
+  tenant_id: string;
+
+  /** @reference SecurityProfileRecord */
+  profile_id: string;
+
+  assigned_at: IsoTimestamp;
 }
 
 type IsoTimestamp = string;
@@ -925,8 +1076,17 @@ interface SyncResponse {
  * @api only
  */
 interface Meta {
+    /** The watermark of the pull: the latest `updated_at` among the rows it read (or `since` when none is newer). Send it back as the next `since`. */
     server_time: IsoTimestamp
     since: IsoTimestamp
+    /** The tenant's current `schema_version`; a client on an older one must upgrade before applying `data` or pushing. */
+    schema_version: number
+    /** True when a `limit` cut the response short; request the next page with `cursor = next_cursor`. Omitted on unpaged responses. */
+    /** @omitNull */
+    has_more?: boolean
+    /** Opaque continuation cursor for the next page, present when `has_more` is true. */
+    /** @omitNull */
+    next_cursor?: string
 }
 
 /**
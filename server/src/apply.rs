@@ -0,0 +1,305 @@
+// This file contains the generic path that applies a change to its domain table.
+
+use rusqlite::{Connection, OptionalExtension, ToSql, params, params_from_iter};
+
+use crate::change_log::ChangeOperation;
+use crate::fetching::SyncedTable;
+use crate::merge::ReplayedRecord;
+use crate::projection::ProjectedRecord;
+use crate::routes::data_result::SYNCED_TABLES;
+
+/// The `status` value that marks a soft-deleted record (a tombstone).
+pub const DELETED_STATUS: &str = "deleted";
+
+/// Look up the domain table for an overlay/change_log `object_name`.
+pub fn synced_table(object_name: &str) -> Option<&'static SyncedTable> {
+    SYNCED_TABLES.iter().find(|table| table.object_name == object_name)
+}
+
+/// Look up a synced table by its SQL table name.
+pub fn synced_table_by_name(table_name: &str) -> Option<&'static SyncedTable> {
+    SYNCED_TABLES.iter().find(|table| table.table_name == table_name)
+}
+
+/// A single change to one record, as stored in `change_log`.
+pub struct RecordChange<'a> {
+    pub tenant_id: &'a str,
+    pub object_name: &'a str,
+    pub record_id: &'a str,
+    pub user_id: &'a str,
+    pub operation: ChangeOperation,
+    /// The JSON merge patch applied to the record's `data` document.
+    pub change_data: &'a str,
+    /// The client's timestamp; becomes `created_at` of a record the change inserts.
+    pub timestamp: &'a str,
+    /// The change's HLC stamp; becomes the record's `updated_at`, which `since` filters on.
+    pub stamped_at: &'a str,
+    /// When set, the change only applies if the record's stored `version` still equals it.
+    /// A record that doesn't exist yet has no version, so any expectation conflicts.
+    pub expected_version: Option<i64>,
+}
+
+/// Reasons a change cannot be applied to its domain table.
+#[derive(Debug)]
+pub enum ApplyError {
+    /// The `object_name` doesn't map to a synced table.
+    UnknownObjectName,
+    /// A `create` targeted a record id that already exists.
+    RecordExists,
+    /// The record id belongs to another tenant (or to global metadata).
+    ForeignRecord,
+    /// A `delete` targeted a table without a `status` column to hold the tombstone.
+    DeleteUnsupported,
+    /// The record's stored version moved on from the `expected_version` the client edited.
+    VersionConflict { expected: i64, current: Option<i64> },
+    /// The record's `data` after the change doesn't match the table's model (e.g. an
+    /// `update` of an unknown id without the required fields); pulls couldn't read it.
+    InvalidDocument(String),
+    Sql(rusqlite::Error),
+}
+
+impl ApplyError {
+    /// Stable error code returned to clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApplyError::UnknownObjectName => "unknown_object_name",
+            ApplyError::RecordExists => "record_exists",
+            ApplyError::ForeignRecord => "foreign_record",
+            ApplyError::DeleteUnsupported => "delete_unsupported",
+            ApplyError::VersionConflict { .. } => "version_conflict",
+            ApplyError::InvalidDocument(_) => "invalid_document",
+            ApplyError::Sql(_) => "domain_apply_failed",
+        }
+    }
+}
+
+impl std::fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApplyError::UnknownObjectName => write!(f, "Provided object_name does not map to a synced table."),
+            ApplyError::RecordExists => write!(f, "A record with this id already exists."),
+            ApplyError::ForeignRecord => write!(f, "A record with this id belongs to another tenant."),
+            ApplyError::DeleteUnsupported => write!(f, "Records of this object type cannot be deleted."),
+            ApplyError::VersionConflict { expected, current: Some(current) } => {
+                write!(f, "Expected version {} but the record is at version {}.", expected, current)
+            }
+            ApplyError::VersionConflict { expected, current: None } => {
+                write!(f, "Expected version {} but the record does not exist.", expected)
+            }
+            ApplyError::InvalidDocument(e) => write!(f, "The record's data does not match its object type: {}", e),
+            ApplyError::Sql(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ApplyError {
+    fn from(e: rusqlite::Error) -> Self {
+        ApplyError::Sql(e)
+    }
+}
+
+/// Apply a change to its domain table.
+/// - `update` merges the JSON patch; if no row exists, INSERT a new record (upsert
+///   semantics for client-generated IDs). Updates to a tombstone are merged but
+///   do not bring the record back: delete wins.
+/// - `create` inserts a new record and refuses to overwrite an existing one.
+/// - `delete` soft-deletes by setting `status` so the tombstone reaches every client.
+///
+/// Every applied change bumps the record's integer `version`. With `expected_version`
+/// set, the change is refused when that version has moved on. Ids are global across
+/// tenants, so a change to an id another tenant owns is refused rather than upserted.
+/// A create or update whose resulting document doesn't decode as the table's model is
+/// refused, so one bad record can't break pulls of the whole tenant; run it in a
+/// savepoint so the refused write is rolled back.
+pub fn apply_change(conn: &Connection, table: &SyncedTable, change: &RecordChange) -> Result<(), ApplyError> {
+    if owned_elsewhere(conn, table, change.tenant_id, change.record_id)? {
+        return Err(ApplyError::ForeignRecord);
+    }
+    if let Some(expected) = change.expected_version {
+        let current = record_version(conn, table, change.tenant_id, change.record_id)?;
+        if current != Some(expected) {
+            return Err(ApplyError::VersionConflict { expected, current });
+        }
+    }
+    match change.operation {
+        ChangeOperation::Create => {
+            if record_exists(conn, table, change.tenant_id, change.record_id)? {
+                return Err(ApplyError::RecordExists);
+            }
+            insert_record(conn, table, change)?;
+            validate_document(conn, table, change.tenant_id, change.record_id)
+        }
+        ChangeOperation::Update => {
+            if !update_record(conn, table, change)? {
+                // No existing row; treat as CREATE and insert a new record.
+                insert_record(conn, table, change)?;
+            }
+            validate_document(conn, table, change.tenant_id, change.record_id)
+        }
+        ChangeOperation::Delete => {
+            if !table.has_status {
+                return Err(ApplyError::DeleteUnsupported);
+            }
+            // Table names come from the generated SYNCED_TABLES list, never from the client.
+            // Deleting a record that was never synced is a no-op; the change_log entry still records intent.
+            conn.execute(
+                &format!(
+                    "UPDATE {} SET status = ?1, version = version + 1, updated_at = ?2 WHERE id = ?3 AND tenant_id = ?4",
+                    table.table_name
+                ),
+                params![DELETED_STATUS, change.stamped_at, change.record_id, change.tenant_id],
+            )?;
+            Ok(())
+        }
+    }
+}
+
+/// Read a record's current `data` document, or `None` if it doesn't exist or is a tombstone.
+pub fn fetch_document(conn: &Connection, table: &SyncedTable, tenant_id: &str, record_id: &str) -> rusqlite::Result<Option<serde_json::Value>> {
+    let status_column = if table.has_status { "status" } else { "NULL" };
+    let row: Option<(String, Option<String>)> = conn
+        .query_row(
+            &format!("SELECT data, {} FROM {} WHERE id = ?1 AND tenant_id = ?2", status_column, table.table_name),
+            params![record_id, tenant_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    Ok(row
+        .filter(|(_, status)| status.as_deref() != Some(DELETED_STATUS))
+        .and_then(|(data, _)| serde_json::from_str(&data).ok()))
+}
+
+/// Read a record's stored `version`, or `None` if it doesn't exist (tombstones included).
+pub fn record_version(conn: &Connection, table: &SyncedTable, tenant_id: &str, record_id: &str) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        &format!("SELECT version FROM {} WHERE id = ?1 AND tenant_id = ?2", table.table_name),
+        params![record_id, tenant_id],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+fn record_exists(conn: &Connection, table: &SyncedTable, tenant_id: &str, record_id: &str) -> rusqlite::Result<bool> {
+    Ok(record_version(conn, table, tenant_id, record_id)?.is_some())
+}
+
+// Check the stored `data` of a record against its table's model.
+fn validate_document(conn: &Connection, table: &SyncedTable, tenant_id: &str, record_id: &str) -> Result<(), ApplyError> {
+    let data: String = conn.query_row(
+        &format!("SELECT data FROM {} WHERE id = ?1 AND tenant_id = ?2", table.table_name),
+        params![record_id, tenant_id],
+        |row| row.get(0),
+    )?;
+    (table.validate_data)(&data).map_err(|e| ApplyError::InvalidDocument(e.to_string()))
+}
+
+// Whether a row with this id exists but isn't the tenant's (NULL tenant_id is global metadata).
+fn owned_elsewhere(conn: &Connection, table: &SyncedTable, tenant_id: &str, record_id: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        &format!("SELECT EXISTS (SELECT 1 FROM {} WHERE id = ?1 AND tenant_id IS NOT ?2)", table.table_name),
+        params![record_id, tenant_id],
+        |row| row.get(0),
+    )
+}
+
+/// Merge the patch into an existing row. Returns false when the row doesn't exist.
+fn update_record(conn: &Connection, table: &SyncedTable, change: &RecordChange) -> rusqlite::Result<bool> {
+    // Table names come from the generated SYNCED_TABLES list, never from the client.
+    let updated = conn.execute(
+        &format!(
+            "UPDATE {} SET data = json_patch(data, ?1), version = version + 1, updated_at = ?2 WHERE id = ?3 AND tenant_id = ?4",
+            table.table_name
+        ),
+        params![change.change_data, change.stamped_at, change.record_id, change.tenant_id],
+    )?;
+    Ok(updated > 0)
+}
+
+fn insert_record(conn: &Connection, table: &SyncedTable, change: &RecordChange) -> Result<(), ApplyError> {
+    insert_row(conn, table, &NewRow {
+        tenant_id: change.tenant_id,
+        record_id: change.record_id,
+        version: 0,
+        created_by: change.user_id,
+        modified_by: change.user_id,
+        created_at: change.timestamp,
+        updated_at: change.stamped_at,
+        data: change.change_data, // initial document
+        deleted: false,
+    })?;
+    Ok(())
+}
+
+/// Write a replayed record back into its (emptied) domain table, tombstones included.
+pub fn restore_record(conn: &Connection, table: &SyncedTable, tenant_id: &str, record_id: &str, record: &ProjectedRecord) -> rusqlite::Result<()> {
+    let (data, deleted) = match &record.state {
+        ReplayedRecord::Live(document) => (document, false),
+        ReplayedRecord::Deleted(document) => (document, true),
+        // Nothing was ever stored for it.
+        ReplayedRecord::Missing => return Ok(()),
+    };
+    insert_row(conn, table, &NewRow {
+        tenant_id,
+        record_id,
+        version: record.version,
+        created_by: &record.created_by,
+        modified_by: &record.modified_by,
+        created_at: &record.created_at,
+        updated_at: &record.updated_at,
+        data: &data.to_string(),
+        deleted,
+    })
+}
+
+/// Delete one record of a tenant from a domain table. Returns the number of rows removed.
+pub fn delete_record_row(conn: &Connection, table: &SyncedTable, tenant_id: &str, record_id: &str) -> rusqlite::Result<usize> {
+    conn.execute(&format!("DELETE FROM {} WHERE id = ?1 AND tenant_id = ?2", table.table_name), params![record_id, tenant_id])
+}
+
+/// Number of rows a tenant has in a domain table.
+pub fn count_tenant_rows(conn: &Connection, table: &SyncedTable, tenant_id: &str) -> rusqlite::Result<usize> {
+    conn.query_row(&format!("SELECT COUNT(1) FROM {} WHERE tenant_id = ?1", table.table_name), params![tenant_id], |row| row.get(0))
+}
+
+// Column values of a new domain row.
+struct NewRow<'a> {
+    tenant_id: &'a str,
+    record_id: &'a str,
+    version: i64,
+    created_by: &'a str,
+    modified_by: &'a str,
+    created_at: &'a str,
+    updated_at: &'a str,
+    data: &'a str,
+    deleted: bool,
+}
+
+fn insert_row(conn: &Connection, table: &SyncedTable, row: &NewRow) -> rusqlite::Result<()> {
+    let mut columns: Vec<&str> = vec!["id", "tenant_id", "version", "created_by", "modified_by", "created_at", "updated_at", "object_name", "data"];
+    let mut values: Vec<&dyn ToSql> = vec![
+        &row.record_id,     // id
+        &row.tenant_id,     // tenant_id
+        &row.version,       // version
+        &row.created_by,    // created_by
+        &row.modified_by,   // modified_by
+        &row.created_at,    // created_at
+        &row.updated_at,    // updated_at
+        &table.object_name, // object_name
+        &row.data,          // data
+    ];
+    if table.has_status {
+        columns.push("status");
+        values.push(if row.deleted { &DELETED_STATUS } else { &"active" }); // status (default for new records)
+    }
+    if table.has_object_type {
+        columns.push("object_type");
+        values.push(&table.object_name); // object_type (using object_name for now)
+    }
+
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{}", i)).collect();
+    conn.execute(
+        &format!("INSERT INTO {} ({}) VALUES ({})", table.table_name, columns.join(", "), placeholders.join(", ")),
+        params_from_iter(values),
+    )?;
+    Ok(())
+}
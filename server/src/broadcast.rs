@@ -0,0 +1,79 @@
+// This file contains the in-process fan-out of committed changes to live subscribers.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use rusqlite::Connection;
+use tokio::sync::broadcast;
+
+use crate::change_log::ChangeLogRecord;
+use crate::fetching::SyncScope;
+use crate::profiles::Channel;
+
+// How many changes a slow subscriber may fall behind before it is marked as lagged.
+// Lagged subscribers recover by re-reading the change_log from their last sequence_id.
+const CHANNEL_CAPACITY: usize = 1024;
+
+// A tenant's channels, keyed like `Channel::key` (`None` is the unrestricted feed).
+type TenantChannels = HashMap<Option<String>, ScopedSender>;
+
+struct ScopedSender {
+    sender: broadcast::Sender<ChangeLogRecord>,
+    scope: SyncScope,
+}
+
+/// One broadcast channel per tenant and security-profile channel, created lazily on
+/// first subscribe. Subscribers of the same channel share it, so each change is checked
+/// against a profile once, not once per client.
+#[derive(Clone, Default)]
+pub struct ChangeBroadcaster {
+    channels: Arc<Mutex<HashMap<String, TenantChannels>>>,
+}
+
+impl ChangeBroadcaster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe to every change committed for `tenant_id` from now on that `channel` may see.
+    pub fn subscribe(&self, tenant_id: &str, channel: &Channel) -> broadcast::Receiver<ChangeLogRecord> {
+        let mut channels = self.channels.lock().unwrap();
+        let scoped = channels
+            .entry(tenant_id.to_string())
+            .or_default()
+            .entry(channel.key.clone())
+            .or_insert_with(|| ScopedSender { sender: broadcast::channel(CHANNEL_CAPACITY).0, scope: channel.scope.clone() });
+        // The latest subscriber read the profile most recently; a redefined profile applies from here on.
+        scoped.scope = channel.scope.clone();
+        scoped.sender.subscribe()
+    }
+
+    /// Publish committed changes to the tenant's subscribers, in order, each channel
+    /// receiving the changes to records its scope allows (judged by the rows in `conn`).
+    /// Must only be called after the enclosing transaction has committed.
+    pub fn publish(&self, conn: &Connection, tenant_id: &str, changes: Vec<ChangeLogRecord>) {
+        let mut channels = self.channels.lock().unwrap();
+        let Some(tenant_channels) = channels.get_mut(tenant_id) else {
+            // Nobody has ever subscribed to this tenant; nothing to deliver.
+            return;
+        };
+        // Drop idle channels so tenants and profiles without live clients don't accumulate.
+        tenant_channels.retain(|_, scoped| scoped.sender.receiver_count() > 0);
+        if tenant_channels.is_empty() {
+            channels.remove(tenant_id);
+            return;
+        }
+        for scoped in tenant_channels.values() {
+            for change in &changes {
+                match scoped.scope.allows(conn, tenant_id, &change.object_name, &change.record_id) {
+                    // `send` only fails when there are no receivers, which we checked above.
+                    Ok(true) => {
+                        let _ = scoped.sender.send(change.clone());
+                    }
+                    Ok(false) => {}
+                    // Withhold what we can't check; clients catch up from the change_log when they reconnect.
+                    Err(e) => eprintln!("Failed to check change {} against a channel: {}", change.id, e),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,290 @@
+// This file contains shared access to the hash-chained `change_log` table.
+
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::compaction;
+use crate::hashing::{self, ChangeHashInput, HashVersion};
+use crate::hlc;
+use crate::schema_version;
+
+// When a tenant has no previous changes, we use a known "genesis" hash
+// as the starting point for the hash chain. This ensures the chain is always
+// valid and verifiable from the very first entry.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000"; // 64 zeros
+
+// Column list shared by every query that maps rows with `change_log_record_from_row`.
+const CHANGE_LOG_COLUMNS: &str = "sequence_id, id, tenant_id, user_id, object_name, record_id, change_data, state_hash, previous_state_hash, created_at, operation, hash_version, hlc, schema_version";
+
+/// What a change does to its record. Stored as lowercase text in `change_log.operation`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeOperation {
+    /// Insert a new record; fails if the record already exists.
+    Create,
+    /// Merge the patch into the record, inserting it if it doesn't exist yet.
+    #[default]
+    Update,
+    /// Soft-delete the record by setting its status to `DELETED_STATUS`.
+    Delete,
+}
+
+impl ChangeOperation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeOperation::Create => "create",
+            ChangeOperation::Update => "update",
+            ChangeOperation::Delete => "delete",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "create" => Some(ChangeOperation::Create),
+            "update" => Some(ChangeOperation::Update),
+            "delete" => Some(ChangeOperation::Delete),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ChangeLogRecord {
+    pub sequence_id: i64,
+    pub id: String,
+    pub tenant_id: String,
+    pub user_id: String,
+    pub object_name: String,
+    pub record_id: String,
+    pub operation: ChangeOperation,
+    pub change_data: Value,
+    pub state_hash: String,
+    pub previous_state_hash: String,
+    /// The client's timestamp for the change. Hashed, but never used for ordering.
+    pub created_at: String,
+    /// How `change_data` was serialized for the content hash.
+    pub hash_version: HashVersion,
+    /// The server's hybrid logical clock stamp from when it accepted the change (see `hlc`).
+    /// `None` for rows accepted before changes were stamped whose `created_at` couldn't be backfilled (see `migrations`).
+    pub hlc: Option<String>,
+    /// The tenant's schema version when the change was accepted (see `schema_version`);
+    /// clients on an older version stop and upgrade. `None` only on rows written outside the server.
+    pub schema_version: Option<i64>,
+}
+
+impl ChangeLogRecord {
+    /// Recompute this row's `state_hash` from its stored fields and `previous_state_hash`,
+    /// using the hash version it was written with.
+    pub fn recompute_state_hash(&self) -> String {
+        let change_hash = hashing::change_hash(self.hash_version, &ChangeHashInput {
+            id: &self.id,
+            tenant_id: &self.tenant_id,
+            user_id: &self.user_id,
+            created_at: &self.created_at,
+            object_name: &self.object_name,
+            record_id: &self.record_id,
+            operation: self.operation,
+            changes: &self.change_data,
+        });
+        hashing::chain_state_hash(&change_hash, &self.previous_state_hash)
+    }
+
+    /// When the change took effect on the server: its HLC stamp, or the client's
+    /// `created_at` for rows accepted before changes were stamped. Domain rows use it as `updated_at`.
+    pub fn stamped_at(&self) -> &str {
+        self.hlc.as_deref().unwrap_or(&self.created_at)
+    }
+}
+
+// Map a result row to our API model. `row.get::<_, T>(index)` extracts a typed column by index.
+pub fn change_log_record_from_row(row: &rusqlite::Row) -> Result<ChangeLogRecord> {
+    let change_data_str: String = row.get(6)?;
+    let operation_str: Option<String> = row.get(10)?;
+    let hash_version: Option<i64> = row.get(11)?;
+    Ok(ChangeLogRecord {
+        sequence_id: row.get(0)?,
+        id: row.get(1)?,
+        tenant_id: row.get(2)?,
+        user_id: row.get(3)?,
+        object_name: row.get(4)?,
+        record_id: row.get(5)?,
+        // Rows written before operations were tracked are plain merge patches.
+        operation: operation_str.as_deref().and_then(ChangeOperation::parse).unwrap_or_default(),
+        // Parse JSON payload; if parsing fails, use JSON null to keep the stream resilient.
+        change_data: serde_json::from_str(&change_data_str).unwrap_or(json!(null)),
+        state_hash: row.get(7)?,
+        previous_state_hash: row.get(8)?,
+        created_at: row.get(9)?,
+        // Rows written before versions were tracked were hashed over serde_json text.
+        hash_version: hash_version.and_then(HashVersion::from_i64).unwrap_or_default(),
+        hlc: row.get(12)?,
+        schema_version: row.get(13)?,
+    })
+}
+
+/// A change written by the server itself rather than synced from a client.
+pub struct NewChange<'a> {
+    pub tenant_id: &'a str,
+    pub user_id: &'a str,
+    pub object_name: &'a str,
+    pub record_id: &'a str,
+    pub operation: ChangeOperation,
+    pub changes: &'a Value,
+    pub created_at: &'a str,
+}
+
+/// Append a server-authored change on top of the tenant's current head, with a fresh id,
+/// a server-computed `state_hash`, an HLC stamp and the tenant's schema version. Run it in
+/// the transaction that applies the change, so the head can't move in between.
+pub fn append_change(conn: &Connection, change: &NewChange) -> Result<ChangeLogRecord> {
+    let (_, previous_state_hash) = chain_head(conn, change.tenant_id)?;
+    let id = Uuid::new_v4().to_string();
+    // The server always hashes with the newest version.
+    let hash_version = HashVersion::JcsArray;
+    let change_hash = hashing::change_hash(hash_version, &ChangeHashInput {
+        id: &id,
+        tenant_id: change.tenant_id,
+        user_id: change.user_id,
+        created_at: change.created_at,
+        object_name: change.object_name,
+        record_id: change.record_id,
+        operation: change.operation,
+        changes: change.changes,
+    });
+    let state_hash = hashing::chain_state_hash(&change_hash, &previous_state_hash);
+    let stamp = hlc::next_stamp(conn, change.tenant_id)?;
+    let schema_version = schema_version::for_change(conn, change.tenant_id, change.object_name)?;
+    conn.execute(
+        "INSERT INTO change_log (id, tenant_id, user_id, object_name, record_id, change_data, state_hash, previous_state_hash, created_at, operation, hash_version, hlc, schema_version) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        params![
+            &id,
+            change.tenant_id,
+            change.user_id,
+            change.object_name,
+            change.record_id,
+            hash_version.changes_text(change.changes),
+            &state_hash,
+            &previous_state_hash,
+            change.created_at,
+            change.operation.as_str(),
+            hash_version.as_i64(),
+            &stamp,
+            schema_version,
+        ],
+    )?;
+    fetch_change(conn, &id)
+}
+
+/// Resolve a `state_hash` to its `sequence_id` for a tenant.
+/// The genesis hash resolves to 0 so that clients with an empty chain can still anchor on it.
+/// Once the change_log has been compacted, the snapshot anchor resolves to its `sequence_id`
+/// and anything older resolves to `None`: the rows after it are gone.
+pub fn find_sequence_id(conn: &Connection, tenant_id: &str, state_hash: &str) -> Result<Option<i64>> {
+    let snapshot = compaction::latest_snapshot(conn, tenant_id)?;
+    if let Some(snapshot) = &snapshot {
+        if snapshot.state_hash == state_hash {
+            return Ok(Some(snapshot.sequence_id));
+        }
+    }
+    let sequence_id = if state_hash == GENESIS_HASH {
+        Some(0)
+    } else {
+        conn.query_row(
+            "SELECT sequence_id FROM change_log WHERE state_hash = ?1 AND tenant_id = ?2",
+            params![state_hash, tenant_id],
+            |row| row.get(0),
+        )
+        .optional()?
+    };
+    Ok(sequence_id.filter(|sequence_id| snapshot.is_none_or(|snapshot| *sequence_id >= snapshot.sequence_id)))
+}
+
+/// Return the tenant's current chain head as `(sequence_id, state_hash)`. With no rows left
+/// this is the latest compaction anchor, or `(0, GENESIS_HASH)` when the tenant has no changes yet.
+pub fn chain_head(conn: &Connection, tenant_id: &str) -> Result<(i64, String)> {
+    let head = conn
+        .query_row(
+            "SELECT sequence_id, state_hash FROM change_log WHERE tenant_id = ?1 ORDER BY sequence_id DESC LIMIT 1",
+            params![tenant_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    match head {
+        Some(head) => Ok(head),
+        None => Ok(chain_start(conn, tenant_id)?),
+    }
+}
+
+/// The tenant's chain position as of `timestamp` (in `hlc::format`): the last row the server
+/// had accepted by then, or the chain start when every row is newer. Rows accepted before
+/// changes were stamped fall back to their client `created_at`. Returns `None` when the
+/// change_log was compacted and no retained row is old enough: the position was pruned.
+pub fn position_at(conn: &Connection, tenant_id: &str, timestamp: &str) -> Result<Option<(i64, String)>> {
+    let position = conn
+        .query_row(
+            "SELECT sequence_id, state_hash FROM change_log WHERE tenant_id = ?1 AND COALESCE(hlc, created_at) <= ?2 ORDER BY sequence_id DESC LIMIT 1",
+            params![tenant_id, timestamp],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    match position {
+        Some(position) => Ok(Some(position)),
+        None if compaction::latest_snapshot(conn, tenant_id)?.is_some() => Ok(None),
+        None => Ok(Some((0, GENESIS_HASH.to_string()))),
+    }
+}
+
+/// Where the tenant's stored chain starts: the latest compaction anchor, or genesis.
+pub fn chain_start(conn: &Connection, tenant_id: &str) -> Result<(i64, String)> {
+    Ok(compaction::latest_snapshot(conn, tenant_id)?
+        .map(|snapshot| (snapshot.sequence_id, snapshot.state_hash))
+        .unwrap_or((0, GENESIS_HASH.to_string())))
+}
+
+/// Fetch a single change by its client-generated id.
+pub fn fetch_change(conn: &Connection, id: &str) -> Result<ChangeLogRecord> {
+    conn.query_row(
+        &format!("SELECT {} FROM change_log WHERE id = ?1", CHANGE_LOG_COLUMNS),
+        params![id],
+        change_log_record_from_row,
+    )
+}
+
+/// Fetch every change to one record, oldest first, optionally only up to `up_to_sequence_id`.
+pub fn fetch_record_changes(
+    conn: &Connection,
+    tenant_id: &str,
+    object_name: &str,
+    record_id: &str,
+    up_to_sequence_id: Option<i64>,
+) -> Result<Vec<ChangeLogRecord>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM change_log WHERE tenant_id = ?1 AND object_name = ?2 AND record_id = ?3 AND sequence_id <= ?4 ORDER BY sequence_id ASC",
+        CHANGE_LOG_COLUMNS
+    ))?;
+    let rows = stmt.query_map(
+        params![tenant_id, object_name, record_id, up_to_sequence_id.unwrap_or(i64::MAX)],
+        change_log_record_from_row,
+    )?;
+    rows.collect()
+}
+
+/// Like `fetch_change`, but returns `None` when no change has that id.
+pub fn find_change(conn: &Connection, id: &str) -> Result<Option<ChangeLogRecord>> {
+    fetch_change(conn, id).optional()
+}
+
+/// Fetch changes for a tenant strictly after `since_sequence_id`, ordered ASC
+/// for safe sequential application. `limit` caps the number of rows; `None` returns them all.
+pub fn fetch_changes_after(conn: &Connection, tenant_id: &str, since_sequence_id: i64, limit: Option<usize>) -> Result<Vec<ChangeLogRecord>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM change_log WHERE tenant_id = ?1 AND sequence_id > ?2 ORDER BY sequence_id ASC LIMIT ?3",
+        CHANGE_LOG_COLUMNS
+    ))?;
+    // A negative LIMIT means no limit in SQLite.
+    let limit = limit.map_or(-1, |limit| limit as i64);
+    let rows = stmt.query_map(params![tenant_id, since_sequence_id, limit], change_log_record_from_row)?;
+    rows.collect()
+}
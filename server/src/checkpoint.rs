@@ -0,0 +1,193 @@
+// This file contains signed checkpoints of each tenant's chain head.
+//
+// A checkpoint says "the server's chain for this tenant had this `state_hash` at
+// this `sequence_id`", signed with the server's Ed25519 key. Clients that pin the
+// public key can check that the head they synced to is one the server vouched for.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{SecondsFormat, Utc};
+use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey, Signature};
+use rand::rngs::OsRng;
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::change_log;
+use crate::hashing::canonical_json;
+
+/// The server's checkpoint signing key, loaded once at startup.
+#[derive(Clone)]
+pub struct CheckpointSigner {
+    key: Arc<SigningKey>,
+}
+
+impl CheckpointSigner {
+    /// Load the hex-encoded secret key at `path`, creating it on first start.
+    pub fn load_or_create(path: &Path) -> io::Result<Self> {
+        match Self::load(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let key = SigningKey::generate(&mut OsRng);
+                write_secret(path, &to_hex(key.as_bytes()))?;
+                Ok(CheckpointSigner { key: Arc::new(key) })
+            }
+            loaded => loaded,
+        }
+    }
+
+    /// Load the hex-encoded secret key at `path`; fails with `NotFound` when there is none.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let bytes: [u8; 32] = from_hex(text.trim())
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "signing key must be 32 hex-encoded bytes"))?;
+        Ok(CheckpointSigner { key: Arc::new(SigningKey::from_bytes(&bytes)) })
+    }
+
+    /// The hex-encoded Ed25519 public key clients pin.
+    pub fn public_key(&self) -> String {
+        to_hex(self.key.verifying_key().as_bytes())
+    }
+
+    fn sign(&self, tenant_id: &str, sequence_id: i64, state_hash: &str, timestamp: &str) -> Checkpoint {
+        let payload = signed_payload(tenant_id, sequence_id, state_hash, timestamp);
+        Checkpoint {
+            tenant_id: tenant_id.to_string(),
+            sequence_id,
+            state_hash: state_hash.to_string(),
+            timestamp: timestamp.to_string(),
+            public_key: self.public_key(),
+            signature: to_hex(&self.key.sign(payload.as_bytes()).to_bytes()),
+        }
+    }
+}
+
+/// A signed statement of a tenant's chain head.
+#[derive(Serialize, Debug, Clone)]
+pub struct Checkpoint {
+    pub tenant_id: String,
+    pub sequence_id: i64,
+    pub state_hash: String,
+    pub timestamp: String,
+    /// Hex-encoded Ed25519 public key of the signer.
+    pub public_key: String,
+    /// Hex-encoded Ed25519 signature over `signed_payload` of the four fields above.
+    pub signature: String,
+}
+
+impl Checkpoint {
+    /// Check the signature against `trusted_public_key` (hex), the server's own key or one
+    /// pinned in advance. The stored `public_key` only labels the signer: whoever can write
+    /// a forged row can also write a key that verifies it.
+    pub fn verify(&self, trusted_public_key: &str) -> bool {
+        let Some(public_key) = from_hex(trusted_public_key).and_then(|bytes| <[u8; 32]>::try_from(bytes).ok()) else {
+            return false;
+        };
+        let Some(signature) = from_hex(&self.signature).and_then(|bytes| <[u8; 64]>::try_from(bytes).ok()) else {
+            return false;
+        };
+        let Ok(verifying_key) = VerifyingKey::from_bytes(&public_key) else {
+            return false;
+        };
+        let payload = signed_payload(&self.tenant_id, self.sequence_id, &self.state_hash, &self.timestamp);
+        verifying_key.verify(payload.as_bytes(), &Signature::from_bytes(&signature)).is_ok()
+    }
+}
+
+/// The exact bytes that are signed: the RFC 8785 canonical JSON of
+/// `{ "sequence_id", "state_hash", "tenant_id", "timestamp" }`.
+pub fn signed_payload(tenant_id: &str, sequence_id: i64, state_hash: &str, timestamp: &str) -> String {
+    canonical_json(&json!({
+        "tenant_id": tenant_id,
+        "sequence_id": sequence_id,
+        "state_hash": state_hash,
+        "timestamp": timestamp,
+    }))
+}
+
+/// Sign and store a checkpoint of the tenant's current head, unless the head
+/// hasn't moved since its last checkpoint (or the tenant has no changes yet).
+pub fn issue_checkpoint(conn: &Connection, signer: &CheckpointSigner, tenant_id: &str) -> Result<Option<Checkpoint>> {
+    let (sequence_id, state_hash) = change_log::chain_head(conn, tenant_id)?;
+    if sequence_id == 0 {
+        return Ok(None);
+    }
+    if let Some(latest) = latest_checkpoint(conn, tenant_id, None)? {
+        if latest.sequence_id == sequence_id {
+            return Ok(None);
+        }
+    }
+
+    let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let checkpoint = signer.sign(tenant_id, sequence_id, &state_hash, &timestamp);
+    conn.execute(
+        "INSERT INTO chain_checkpoints (tenant_id, sequence_id, state_hash, timestamp, public_key, signature) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            &checkpoint.tenant_id,
+            checkpoint.sequence_id,
+            &checkpoint.state_hash,
+            &checkpoint.timestamp,
+            &checkpoint.public_key,
+            &checkpoint.signature,
+        ],
+    )?;
+    Ok(Some(checkpoint))
+}
+
+/// Checkpoint every tenant whose head moved since its last checkpoint.
+pub fn issue_due_checkpoints(conn: &Connection, signer: &CheckpointSigner) -> Result<Vec<Checkpoint>> {
+    let mut stmt = conn.prepare("SELECT id FROM tenants")?;
+    let tenant_ids: Vec<String> = stmt.query_map([], |row| row.get(0))?.collect::<Result<_>>()?;
+    let mut issued = Vec::new();
+    for tenant_id in tenant_ids {
+        issued.extend(issue_checkpoint(conn, signer, &tenant_id)?);
+    }
+    Ok(issued)
+}
+
+/// The tenant's most recent checkpoint, optionally only among those at or before `up_to_sequence_id`.
+pub fn latest_checkpoint(conn: &Connection, tenant_id: &str, up_to_sequence_id: Option<i64>) -> Result<Option<Checkpoint>> {
+    conn.query_row(
+        "SELECT tenant_id, sequence_id, state_hash, timestamp, public_key, signature FROM chain_checkpoints
+         WHERE tenant_id = ?1 AND sequence_id <= ?2 ORDER BY sequence_id DESC LIMIT 1",
+        params![tenant_id, up_to_sequence_id.unwrap_or(i64::MAX)],
+        |row| Ok(Checkpoint {
+            tenant_id: row.get(0)?,
+            sequence_id: row.get(1)?,
+            state_hash: row.get(2)?,
+            timestamp: row.get(3)?,
+            public_key: row.get(4)?,
+            signature: row.get(5)?,
+        }),
+    )
+    .optional()
+}
+
+// The secret key is only readable by the server's user.
+fn write_secret(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [_, _] => std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok()),
+            _ => None,
+        })
+        .collect()
+}
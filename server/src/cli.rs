@@ -0,0 +1,220 @@
+// This file contains the offline subcommands of the server binary.
+//
+// Usage:
+//   fieldprime_server                          start the HTTP server
+//   fieldprime_server verify-chain <tenant_id> [public_key]  re-walk a tenant's hash chain and print the report
+//   fieldprime_server compact [retention_days] fold change_log rows older than the retention window into snapshots
+//   fieldprime_server check-projection <tenant_id>   diff the replayed change_log against the domain tables
+//   fieldprime_server rebuild-projection <tenant_id> replace a tenant's domain rows with the replayed change_log
+//   fieldprime_server define-profile <tenant_id> <name> <definition_json>  create or replace a security profile
+//   fieldprime_server assign-profile <tenant_id> <user_id> [name]          assign a user's security profile (none: unassign)
+//   fieldprime_server list-profiles <tenant_id>                            print a tenant's security profiles
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::Connection;
+
+use crate::checkpoint::CheckpointSigner;
+use crate::compaction;
+use crate::profiles;
+use crate::projection;
+use crate::verify;
+
+/// Run the subcommand named in `args` (without the program name).
+/// Returns the process exit code, or `None` when no subcommand was given and the server should start.
+pub fn run(args: &[String], db_path: &str, signing_key_path: &str) -> Option<i32> {
+    let (command, rest) = args.split_first()?;
+    let code = match command.as_str() {
+        "verify-chain" => verify_chain(rest, db_path, signing_key_path),
+        "compact" => compact(rest, db_path),
+        "check-projection" => check_projection(rest, db_path),
+        "rebuild-projection" => rebuild_projection(rest, db_path),
+        "define-profile" => define_profile(rest, db_path),
+        "assign-profile" => assign_profile(rest, db_path),
+        "list-profiles" => list_profiles(rest, db_path),
+        _ => {
+            eprintln!("Unknown command: {}", command);
+            eprintln!(
+                "Usage: fieldprime_server [verify-chain <tenant_id> [public_key] | compact [retention_days] | check-projection <tenant_id> | rebuild-projection <tenant_id> \
+                 | define-profile <tenant_id> <name> <definition_json> | assign-profile <tenant_id> <user_id> [name] | list-profiles <tenant_id>]"
+            );
+            2
+        }
+    };
+    Some(code)
+}
+
+/// Exit codes: 0 when the chain verifies, 1 when a link is broken or the latest checkpoint
+/// doesn't hold, 2 on usage, key or database errors.
+/// Checkpoint signatures are checked against the pinned `public_key` (hex) when given,
+/// otherwise against the server's existing signing key. The audit never creates a key.
+fn verify_chain(args: &[String], db_path: &str, signing_key_path: &str) -> i32 {
+    let (tenant_id, public_key) = match args {
+        [tenant_id] => match CheckpointSigner::load(Path::new(signing_key_path)) {
+            Ok(signer) => (tenant_id, signer.public_key()),
+            Err(e) => {
+                eprintln!("Failed to load the checkpoint signing key at {}: {}", signing_key_path, e);
+                eprintln!("Pass the server's public key instead: fieldprime_server verify-chain <tenant_id> <public_key>");
+                return 2;
+            }
+        },
+        [tenant_id, public_key] => (tenant_id, public_key.clone()),
+        _ => {
+            eprintln!("Usage: fieldprime_server verify-chain <tenant_id> [public_key]");
+            return 2;
+        }
+    };
+    let report = Connection::open(db_path).and_then(|conn| verify::verify_chain(&Mutex::new(conn), tenant_id, &public_key));
+    match report {
+        Ok(report) => {
+            println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+            if report.valid { 0 } else { 1 }
+        }
+        Err(e) => {
+            eprintln!("Failed to verify chain: {}", e);
+            2
+        }
+    }
+}
+
+/// Exit codes: 0 on success, 2 on usage or database errors.
+fn compact(args: &[String], db_path: &str) -> i32 {
+    let retention_days = match args {
+        [] => compaction::DEFAULT_RETENTION_DAYS,
+        [days] => match days.parse::<i64>() {
+            Ok(days) if days >= 0 => days,
+            _ => {
+                eprintln!("retention_days must be a non-negative number of days");
+                return 2;
+            }
+        },
+        _ => {
+            eprintln!("Usage: fieldprime_server compact [retention_days]");
+            return 2;
+        }
+    };
+    let snapshots = Connection::open(db_path).and_then(|mut conn| compaction::compact_all(&mut conn, retention_days));
+    match snapshots {
+        Ok(snapshots) => {
+            println!("{}", serde_json::to_string_pretty(&snapshots).unwrap_or_default());
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to compact change_log: {}", e);
+            2
+        }
+    }
+}
+
+/// Exit codes: 0 when the tables match the chain, 1 when records differ, 2 on usage or database errors.
+fn check_projection(args: &[String], db_path: &str) -> i32 {
+    let [tenant_id] = args else {
+        eprintln!("Usage: fieldprime_server check-projection <tenant_id>");
+        return 2;
+    };
+    let report = Connection::open(db_path).and_then(|conn| projection::check_projection(&conn, tenant_id));
+    match report {
+        Ok(report) => {
+            println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+            if report.consistent { 0 } else { 1 }
+        }
+        Err(e) => {
+            eprintln!("Failed to check projection: {}", e);
+            2
+        }
+    }
+}
+
+/// Exit codes: 0 on success, 2 on usage or database errors (nothing is changed then).
+fn rebuild_projection(args: &[String], db_path: &str) -> i32 {
+    let [tenant_id] = args else {
+        eprintln!("Usage: fieldprime_server rebuild-projection <tenant_id>");
+        return 2;
+    };
+    let report = Connection::open(db_path).and_then(|mut conn| {
+        let tx = conn.transaction()?;
+        let report = projection::rebuild_projection(&tx, tenant_id)?;
+        tx.commit()?;
+        Ok(report)
+    });
+    match report {
+        Ok(report) => {
+            println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to rebuild projection: {}", e);
+            2
+        }
+    }
+}
+
+/// Exit codes: 0 on success, 2 on usage, validation or database errors.
+fn define_profile(args: &[String], db_path: &str) -> i32 {
+    let [tenant_id, name, definition] = args else {
+        eprintln!("Usage: fieldprime_server define-profile <tenant_id> <name> <definition_json>");
+        return 2;
+    };
+    let definition = match serde_json::from_str(definition) {
+        Ok(definition) => definition,
+        Err(e) => {
+            eprintln!("definition_json is not valid JSON: {}", e);
+            return 2;
+        }
+    };
+    let profile = Connection::open(db_path)
+        .map_err(profiles::ProfileError::Sql)
+        .and_then(|conn| profiles::define_profile(&conn, tenant_id, name, &definition));
+    match profile {
+        Ok(profile) => {
+            println!("{}", serde_json::to_string_pretty(&profile).unwrap_or_default());
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to define profile: {}", e);
+            2
+        }
+    }
+}
+
+/// Exit codes: 0 on success, 2 on usage, lookup or database errors.
+fn assign_profile(args: &[String], db_path: &str) -> i32 {
+    let (tenant_id, user_id, name) = match args {
+        [tenant_id, user_id] => (tenant_id, user_id, None),
+        [tenant_id, user_id, name] => (tenant_id, user_id, Some(name.as_str())),
+        _ => {
+            eprintln!("Usage: fieldprime_server assign-profile <tenant_id> <user_id> [name]");
+            return 2;
+        }
+    };
+    let assigned = Connection::open(db_path)
+        .map_err(profiles::ProfileError::Sql)
+        .and_then(|conn| profiles::assign_profile(&conn, tenant_id, user_id, name));
+    match assigned {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Failed to assign profile: {}", e);
+            2
+        }
+    }
+}
+
+/// Exit codes: 0 on success, 2 on usage or database errors.
+fn list_profiles(args: &[String], db_path: &str) -> i32 {
+    let [tenant_id] = args else {
+        eprintln!("Usage: fieldprime_server list-profiles <tenant_id>");
+        return 2;
+    };
+    let profiles = Connection::open(db_path).and_then(|conn| profiles::list_profiles(&conn, tenant_id));
+    match profiles {
+        Ok(profiles) => {
+            println!("{}", serde_json::to_string_pretty(&profiles).unwrap_or_default());
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to list profiles: {}", e);
+            2
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// This file contains change_log compaction and the snapshots it leaves behind.
+//
+// Compaction folds a tenant's oldest change_log rows into a snapshot of every
+// record they touched, anchored at the `state_hash` of the last folded row, and
+// then deletes those rows. The anchor takes the place of `GENESIS_HASH` as the
+// start of the tenant's chain: clients anchored at or after it keep pulling
+// deltas, older clients are told to bootstrap.
+
+use std::collections::BTreeMap;
+
+use chrono::{Duration, SecondsFormat, Utc};
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::change_log::{self, ChangeLogRecord};
+use crate::hlc;
+use crate::merge::{self, ReplayedRecord};
+use crate::projection::{Projection, ProjectedRecord};
+
+/// Rows younger than this are never compacted.
+pub const DEFAULT_RETENTION_DAYS: i64 = 90;
+
+/// A compaction anchor: the chain position the tenant's remaining rows start from.
+#[derive(Serialize, Debug, Clone)]
+pub struct Snapshot {
+    pub id: String,
+    pub tenant_id: String,
+    /// The `sequence_id` and `state_hash` of the last compacted row.
+    pub sequence_id: i64,
+    pub state_hash: String,
+    pub created_at: String,
+    /// How many change_log rows this compaction removed.
+    pub pruned_rows: i64,
+}
+
+fn snapshot_from_row(row: &rusqlite::Row) -> Result<Snapshot> {
+    Ok(Snapshot {
+        id: row.get(0)?,
+        tenant_id: row.get(1)?,
+        sequence_id: row.get(2)?,
+        state_hash: row.get(3)?,
+        created_at: row.get(4)?,
+        pruned_rows: row.get(5)?,
+    })
+}
+
+/// The tenant's most recent snapshot, or `None` if its change_log was never compacted.
+pub fn latest_snapshot(conn: &Connection, tenant_id: &str) -> Result<Option<Snapshot>> {
+    conn.query_row(
+        "SELECT id, tenant_id, sequence_id, state_hash, created_at, pruned_rows FROM change_log_snapshots
+         WHERE tenant_id = ?1 ORDER BY sequence_id DESC LIMIT 1",
+        params![tenant_id],
+        snapshot_from_row,
+    )
+    .optional()
+}
+
+// Column list shared by every query that maps rows with `entry_from_row`.
+const ENTRY_COLUMNS: &str = "object_name, record_id, data, deleted, version, created_by, created_at, modified_by, updated_at";
+
+fn entry_from_row(row: &rusqlite::Row) -> Result<((String, String), ProjectedRecord)> {
+    let data: String = row.get(2)?;
+    let document: Value = serde_json::from_str(&data).unwrap_or_default();
+    let deleted: bool = row.get(3)?;
+    Ok(((row.get(0)?, row.get(1)?), ProjectedRecord {
+        state: if deleted { ReplayedRecord::Deleted(document) } else { ReplayedRecord::Live(document) },
+        version: row.get(4)?,
+        created_by: row.get(5)?,
+        created_at: row.get(6)?,
+        modified_by: row.get(7)?,
+        updated_at: row.get(8)?,
+    }))
+}
+
+/// A record as captured by a snapshot, or `None` if the snapshot has no entry for it.
+pub fn snapshot_record(conn: &Connection, snapshot_id: &str, object_name: &str, record_id: &str) -> Result<Option<ProjectedRecord>> {
+    conn.query_row(
+        &format!("SELECT {} FROM change_log_snapshot_entries WHERE snapshot_id = ?1 AND object_name = ?2 AND record_id = ?3", ENTRY_COLUMNS),
+        params![snapshot_id, object_name, record_id],
+        entry_from_row,
+    )
+    .optional()
+    .map(|entry| entry.map(|(_, record)| record))
+}
+
+/// Every record captured by a snapshot.
+pub fn snapshot_records(conn: &Connection, snapshot_id: &str) -> Result<Projection> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM change_log_snapshot_entries WHERE snapshot_id = ?1", ENTRY_COLUMNS))?;
+    let rows = stmt.query_map(params![snapshot_id], entry_from_row)?;
+    rows.collect()
+}
+
+/// Rebuild a record's state as of `up_to_sequence_id` (or the head), starting from the
+/// latest snapshot. Returns `None` when that position was compacted away.
+pub fn record_state_at(
+    conn: &Connection,
+    tenant_id: &str,
+    object_name: &str,
+    record_id: &str,
+    up_to_sequence_id: Option<i64>,
+) -> Result<Option<ReplayedRecord>> {
+    let start = match latest_snapshot(conn, tenant_id)? {
+        Some(snapshot) if up_to_sequence_id.is_some_and(|up_to| up_to < snapshot.sequence_id) => return Ok(None),
+        Some(snapshot) => snapshot_record(conn, &snapshot.id, object_name, record_id)?.map(|record| record.state).unwrap_or_default(),
+        None => ReplayedRecord::Missing,
+    };
+    // Rows at or before the snapshot were deleted, so this only returns the ones after it.
+    let changes = change_log::fetch_record_changes(conn, tenant_id, object_name, record_id, up_to_sequence_id)?;
+    Ok(Some(merge::replay(start, &changes)))
+}
+
+/// Compact one tenant: fold every row before the first row created at or after `cutoff`
+/// into a new snapshot and delete those rows. Run inside a transaction.
+/// Returns `None` when there is nothing old enough to compact.
+pub fn compact_tenant(conn: &Connection, tenant_id: &str, cutoff: &str) -> Result<Option<Snapshot>> {
+    // Rows accepted before changes were HLC-stamped only have client timestamps, so stop
+    // at the first recent row rather than pruning every old row: the remaining chain must
+    // stay contiguous.
+    let anchor: Option<(i64, String)> = conn
+        .query_row(
+            "SELECT sequence_id, state_hash FROM change_log WHERE tenant_id = ?1 AND sequence_id < COALESCE(
+                 (SELECT MIN(sequence_id) FROM change_log WHERE tenant_id = ?1 AND COALESCE(hlc, created_at) >= ?2), 9223372036854775807)
+             ORDER BY sequence_id DESC LIMIT 1",
+            params![tenant_id, cutoff],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let Some((anchor_sequence_id, anchor_state_hash)) = anchor else {
+        return Ok(None);
+    };
+
+    let previous = latest_snapshot(conn, tenant_id)?;
+    let pruned = change_log::fetch_changes_after(conn, tenant_id, previous.as_ref().map_or(0, |s| s.sequence_id), None)?
+        .into_iter()
+        .take_while(|change| change.sequence_id <= anchor_sequence_id)
+        .collect::<Vec<_>>();
+
+    let snapshot = Snapshot {
+        id: Uuid::new_v4().to_string(),
+        tenant_id: tenant_id.to_string(),
+        sequence_id: anchor_sequence_id,
+        state_hash: anchor_state_hash,
+        created_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        pruned_rows: pruned.len() as i64,
+    };
+    conn.execute(
+        "INSERT INTO change_log_snapshots (id, tenant_id, sequence_id, state_hash, created_at, pruned_rows) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![&snapshot.id, &snapshot.tenant_id, snapshot.sequence_id, &snapshot.state_hash, &snapshot.created_at, snapshot.pruned_rows],
+    )?;
+
+    // Start from the previous snapshot's records, then fold in the pruned rows record by record.
+    if let Some(previous) = &previous {
+        conn.execute(
+            &format!(
+                "INSERT INTO change_log_snapshot_entries (snapshot_id, {0}) SELECT ?1, {0} FROM change_log_snapshot_entries WHERE snapshot_id = ?2",
+                ENTRY_COLUMNS
+            ),
+            params![&snapshot.id, &previous.id],
+        )?;
+    }
+    let mut by_record: BTreeMap<(&str, &str), Vec<&ChangeLogRecord>> = BTreeMap::new();
+    for change in &pruned {
+        by_record.entry((change.object_name.as_str(), change.record_id.as_str())).or_default().push(change);
+    }
+    for ((object_name, record_id), changes) in by_record {
+        let mut record = match &previous {
+            Some(previous) => snapshot_record(conn, &previous.id, object_name, record_id)?,
+            None => None,
+        };
+        for change in changes {
+            ProjectedRecord::apply(&mut record, change);
+        }
+        // Only deletes of a record that never existed; there is nothing to keep.
+        let Some(record) = record else { continue };
+        let (document, deleted) = match &record.state {
+            ReplayedRecord::Live(document) => (document, false),
+            ReplayedRecord::Deleted(document) => (document, true),
+            ReplayedRecord::Missing => continue,
+        };
+        conn.execute(
+            "DELETE FROM change_log_snapshot_entries WHERE snapshot_id = ?1 AND object_name = ?2 AND record_id = ?3",
+            params![&snapshot.id, object_name, record_id],
+        )?;
+        conn.execute(
+            &format!("INSERT INTO change_log_snapshot_entries (snapshot_id, {}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)", ENTRY_COLUMNS),
+            params![
+                &snapshot.id,
+                object_name,
+                record_id,
+                document.to_string(),
+                deleted,
+                record.version,
+                &record.created_by,
+                &record.created_at,
+                &record.modified_by,
+                &record.updated_at,
+            ],
+        )?;
+    }
+
+    // Only the latest snapshot's records are needed; older snapshots keep their anchors.
+    if let Some(previous) = &previous {
+        conn.execute("DELETE FROM change_log_snapshot_entries WHERE snapshot_id = ?1", params![&previous.id])?;
+    }
+    conn.execute(
+        "DELETE FROM change_log WHERE tenant_id = ?1 AND sequence_id <= ?2",
+        params![tenant_id, anchor_sequence_id],
+    )?;
+    Ok(Some(snapshot))
+}
+
+/// Compact every tenant's rows older than `retention_days`, one transaction per tenant.
+pub fn compact_all(conn: &mut Connection, retention_days: i64) -> Result<Vec<Snapshot>> {
+    let cutoff = hlc::format(Utc::now() - Duration::days(retention_days));
+    let tenant_ids: Vec<String> = {
+        let mut stmt = conn.prepare("SELECT id FROM tenants")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect::<Result<_>>()?
+    };
+    let mut snapshots = Vec::new();
+    for tenant_id in tenant_ids {
+        let tx = conn.transaction()?;
+        let snapshot = compact_tenant(&tx, &tenant_id, &cutoff)?;
+        tx.commit()?;
+        snapshots.extend(snapshot);
+    }
+    Ok(snapshots)
+}
@@ -1,18 +1,520 @@
 // This file contains generic data fetching logic.
 
-use rusqlite::{Connection, Result, params};
+use std::collections::{BTreeMap, BTreeSet};
+
+use rusqlite::{Connection, Result, params, params_from_iter};
+use rusqlite::types::Value as SqlValue;
+use serde_json::Value;
+
+use crate::apply;
 
 pub fn fetch_all<T>(
     conn: &Connection,
     table_name: &str,
     tenant_id: &str,
     since: &str,
+    page: &mut DataPage,
     decoder: fn(&rusqlite::Row) -> Result<T>
 ) -> Result<Vec<T>> {
-    let mut stmt = conn.prepare(&format!(
-        "SELECT * FROM {} WHERE tenant_id=?1 AND updated_at>?2",
+    // Tables before the cursor's table were fully sent on earlier pages,
+    // and tables after a full page are left for the next one.
+    if page.next.is_some() || page.skips(table_name) || !page.scope.includes(table_name) {
+        return Ok(Vec::new());
+    }
+    // A cursor in a table the scope leaves out resumes at the start of the next one.
+    let after = page.after.take().filter(|after| after.table_name == table_name).unwrap_or_else(|| TableCursor::start_of(table_name));
+
+    // Rows are paged in `(updated_at, id)` order so a cursor is a stable position even when
+    // several rows share a timestamp. One extra row tells us whether the table has more.
+    // A negative LIMIT means no limit in SQLite.
+    let fetch_limit = page.remaining.map_or(-1, |remaining| remaining as i64 + 1);
+    let mut values: Vec<SqlValue> = vec![
+        tenant_id.to_string().into(),
+        since.to_string().into(),
+        after.updated_at.clone().into(),
+        after.id.clone().into(),
+        fetch_limit.into(),
+        page.until.clone().into(),
+    ];
+    let mut sql = format!(
+        "SELECT * FROM {} WHERE tenant_id=?1 AND updated_at>?2 AND (?6 IS NULL OR updated_at<=?6) AND (updated_at, id) > (?3, ?4)",
         table_name
-    ))?;
-    let rows = stmt.query_map(params![tenant_id, since], decoder)?;
-    rows.collect()
+    );
+    for filter in page.scope.filters(table_name) {
+        sql.push_str(" AND ");
+        sql.push_str(&filter.to_sql(&mut values));
+    }
+    sql.push_str(" ORDER BY updated_at, id LIMIT ?5");
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(params_from_iter(values), |row| {
+        Ok((decoder(row)?, row.get::<_, String>("updated_at")?, row.get::<_, String>("id")?))
+    })?;
+    let mut rows: Vec<(T, String, String)> = rows.collect::<Result<_>>()?;
+
+    if let Some(remaining) = page.remaining {
+        if rows.len() > remaining {
+            rows.truncate(remaining);
+            page.next = Some(match rows.last() {
+                Some((_, updated_at, id)) => TableCursor { table_name: table_name.to_string(), updated_at: updated_at.clone(), id: id.clone() },
+                None => after,
+            });
+        }
+        page.remaining = Some(remaining - rows.len());
+    }
+    Ok(rows.into_iter().map(|(record, _, _)| record).collect())
+}
+
+/// A position inside one table for paged `GET /sync` reads: the last
+/// `(updated_at, id)` sent. Serialized as `table|updated_at|id`.
+#[derive(Debug, Clone)]
+pub struct TableCursor {
+    pub table_name: String,
+    pub updated_at: String,
+    pub id: String,
+}
+
+impl TableCursor {
+    /// A position before every row of `table_name`.
+    pub fn start_of(table_name: &str) -> Self {
+        TableCursor { table_name: table_name.to_string(), updated_at: String::new(), id: String::new() }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        // Ids come last so they may contain the separator.
+        let mut parts = value.splitn(3, '|');
+        Some(TableCursor {
+            table_name: parts.next()?.to_string(),
+            updated_at: parts.next()?.to_string(),
+            id: parts.next()?.to_string(),
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        format!("{}|{}|{}", self.table_name, self.updated_at, self.id)
+    }
+}
+
+/// The row budget for one `get_data_result` call, shared across its tables in order.
+/// Tables are read in `SYNCED_TABLES` order, resuming at `after`.
+pub struct DataPage {
+    remaining: Option<usize>,
+    /// The tables and rows the pull asked for; other tables come back empty.
+    scope: SyncScope,
+    /// Rows updated after this watermark are left out; see `watermark`.
+    until: Option<String>,
+    after: Option<TableCursor>,
+    /// Set once the cursor's table has been reached; tables before it are skipped.
+    reached_after: bool,
+    /// Where the next page starts, set when this page filled up.
+    pub next: Option<TableCursor>,
+}
+
+impl DataPage {
+    /// Read every row of every table.
+    pub fn unlimited() -> Self {
+        DataPage { remaining: None, scope: SyncScope::default(), until: None, after: None, reached_after: true, next: None }
+    }
+
+    /// Read at most `limit` rows, starting after `after` (or from the first table).
+    pub fn new(limit: Option<usize>, after: Option<TableCursor>) -> Self {
+        DataPage { remaining: limit, scope: SyncScope::default(), until: None, reached_after: after.is_none(), after, next: None }
+    }
+
+    /// Only read rows with `updated_at <= watermark`, so every page of a pull shares one cut.
+    pub fn up_to(mut self, watermark: &str) -> Self {
+        self.until = Some(watermark.to_string());
+        self
+    }
+
+    /// Only read the tables and rows `scope` selects.
+    pub fn within(mut self, scope: SyncScope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    fn skips(&mut self, table_name: &str) -> bool {
+        if !self.reached_after {
+            match &self.after {
+                Some(after) if after.table_name == table_name => self.reached_after = true,
+                _ => return true,
+            }
+        }
+        false
+    }
+
+    pub fn has_more(&self) -> bool {
+        self.next.is_some()
+    }
+}
+
+/// The latest `updated_at` across the tenant's synced tables, or `since` when nothing is
+/// newer. Every accepted change stamps its row with the tenant's next HLC stamp, which is
+/// later than all earlier ones, so rows written after this read sort after the watermark:
+/// reading `since < updated_at <= watermark` and then pulling from the watermark neither
+/// skips nor repeats a row. Read it in the same transaction as the rows.
+pub fn watermark(conn: &Connection, tenant_id: &str, since: &str, tables: &[SyncedTable]) -> Result<String> {
+    let latest = latest_update(conn, tenant_id, tables)?;
+    Ok(latest.filter(|latest| latest.as_str() > since).unwrap_or_else(|| since.to_string()))
+}
+
+/// The latest `updated_at` across the tenant's rows in `tables`, if it has any.
+pub fn latest_update(conn: &Connection, tenant_id: &str, tables: &[SyncedTable]) -> Result<Option<String>> {
+    let latest = tables
+        .iter()
+        .map(|table| format!("SELECT MAX(updated_at) AS updated_at FROM {} WHERE tenant_id = ?1", table.table_name))
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ");
+    conn.query_row(&format!("SELECT MAX(updated_at) FROM ({})", latest), params![tenant_id], |row| row.get(0))
+}
+
+/// Which tables a pull reads and which of their rows, for clients that replicate only part
+/// of the tenant (e.g. a tech's phone that never shows pricebooks). The default reads everything.
+#[derive(Debug, Default, Clone)]
+pub struct SyncScope {
+    /// `None` reads every synced table.
+    tables: Option<BTreeSet<String>>,
+    /// Conditions on the `data` document, by table name; a row must match all of its table's.
+    filters: BTreeMap<String, Vec<FieldFilter>>,
+}
+
+impl SyncScope {
+    /// Parse the `tables` and `filters` query parameters of GET /sync.
+    ///
+    /// `tables` is a comma-separated list of table names as they appear in `ResponseData`
+    /// (e.g. `jobs,calendar_events`). `filters` is a JSON object keyed by table name, then
+    /// by field of the `data` document (dots reach into nested objects). A field maps to
+    /// the value it must equal or to an object of operators (`eq`, `ne`, `lt`, `lte`,
+    /// `gt`, `gte`, `in`), e.g. `{"jobs":{"assigned_tech_id":"u1"},
+    /// "calendar_events":{"start_time":{"gte":"2024-05-01","lt":"2024-06-01"}}}`.
+    pub fn parse(tables: Option<&str>, filters: Option<&str>) -> Result<Self, ScopeError> {
+        let mut scope = SyncScope::default();
+        if let Some(tables) = tables {
+            scope.select(tables.split(',').map(str::trim).filter(|table_name| !table_name.is_empty()))?;
+        }
+        if let Some(filters) = filters {
+            let filters = serde_json::from_str(filters).map_err(|e| ScopeError::InvalidFilter(format!("filters must be a JSON object: {}", e)))?;
+            scope.add_filters(filters)?;
+        }
+        Ok(scope)
+    }
+
+    /// Parse a stored definition, `{"tables": [..], "filters": {..}}`, with both keys
+    /// optional and meaning the same as the query parameters of `parse`.
+    pub fn from_definition(definition: &Value) -> Result<Self, ScopeError> {
+        let mut scope = SyncScope::default();
+        let Value::Object(definition) = definition else {
+            return Err(ScopeError::InvalidFilter("A definition must be a JSON object with optional tables and filters.".to_string()));
+        };
+        if let Some((key, _)) = definition.iter().find(|(key, _)| *key != "tables" && *key != "filters") {
+            return Err(ScopeError::InvalidFilter(format!("Unknown definition key \"{}\".", key)));
+        }
+        match definition.get("tables") {
+            None => {}
+            Some(Value::Array(tables)) => {
+                let names: Option<Vec<&str>> = tables.iter().map(Value::as_str).collect();
+                let names = names.ok_or_else(|| ScopeError::InvalidFilter("tables must be an array of table names.".to_string()))?;
+                scope.select(names.into_iter())?;
+            }
+            Some(_) => return Err(ScopeError::InvalidFilter("tables must be an array of table names.".to_string())),
+        }
+        if let Some(filters) = definition.get("filters") {
+            scope.add_filters(filters.clone())?;
+        }
+        Ok(scope)
+    }
+
+    /// Narrow this scope to what `other` allows too: only tables both select, and rows
+    /// matching both sets of filters.
+    pub fn restrict(mut self, other: &SyncScope) -> Self {
+        self.tables = match (self.tables, &other.tables) {
+            (None, tables) => tables.clone(),
+            (Some(tables), None) => Some(tables),
+            (Some(tables), Some(other)) => Some(tables.intersection(other).cloned().collect()),
+        };
+        for (table_name, filters) in &other.filters {
+            self.filters.entry(table_name.clone()).or_default().extend(filters.iter().cloned());
+        }
+        self
+    }
+
+    /// True when the scope reads every row of every table.
+    pub fn is_unrestricted(&self) -> bool {
+        self.tables.is_none() && self.filters.values().all(Vec::is_empty)
+    }
+
+    pub fn includes(&self, table_name: &str) -> bool {
+        self.tables.as_ref().is_none_or(|tables| tables.contains(table_name))
+    }
+
+    /// Whether a record is in scope, judged by its current row. Records of unsynced
+    /// objects are only in scope when the scope is unrestricted.
+    pub fn allows(&self, conn: &Connection, tenant_id: &str, object_name: &str, record_id: &str) -> Result<bool> {
+        if self.is_unrestricted() {
+            return Ok(true);
+        }
+        let Some(table) = apply::synced_table(object_name) else {
+            return Ok(false);
+        };
+        if !self.includes(table.table_name) {
+            return Ok(false);
+        }
+        let filters = self.filters(table.table_name);
+        if filters.is_empty() {
+            return Ok(true);
+        }
+        let mut values: Vec<SqlValue> = vec![tenant_id.to_string().into(), record_id.to_string().into()];
+        let mut sql = format!("SELECT EXISTS (SELECT 1 FROM {} WHERE tenant_id=?1 AND id=?2", table.table_name);
+        for filter in filters {
+            sql.push_str(" AND ");
+            sql.push_str(&filter.to_sql(&mut values));
+        }
+        sql.push(')');
+        conn.query_row(&sql, params_from_iter(values), |row| row.get(0))
+    }
+
+    fn filters(&self, table_name: &str) -> &[FieldFilter] {
+        self.filters.get(table_name).map_or(&[], Vec::as_slice)
+    }
+
+    fn select<'a>(&mut self, table_names: impl Iterator<Item = &'a str>) -> Result<(), ScopeError> {
+        let mut selected = BTreeSet::new();
+        for table_name in table_names {
+            selected.insert(known_table(table_name)?);
+        }
+        self.tables = Some(selected);
+        Ok(())
+    }
+
+    fn add_filters(&mut self, filters: Value) -> Result<(), ScopeError> {
+        let Value::Object(filters) = filters else {
+            return Err(ScopeError::InvalidFilter("filters must be a JSON object keyed by table name.".to_string()));
+        };
+        for (table_name, fields) in filters {
+            let table_name = known_table(&table_name)?;
+            let Value::Object(fields) = fields else {
+                return Err(ScopeError::InvalidFilter(format!("filters.{} must be an object keyed by field.", table_name)));
+            };
+            let mut table_filters = Vec::new();
+            for (field, condition) in fields {
+                if field.split('.').any(|part| part.is_empty() || !part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')) {
+                    return Err(ScopeError::InvalidFilter(format!("filters.{}: \"{}\" is not a field name.", table_name, field)));
+                }
+                match condition {
+                    Value::Object(operators) => {
+                        for (operator, value) in operators {
+                            table_filters.push(FieldFilter::new(&table_name, &field, &operator, value)?);
+                        }
+                    }
+                    value => table_filters.push(FieldFilter::new(&table_name, &field, "eq", value)?),
+                }
+            }
+            self.filters.entry(table_name).or_default().extend(table_filters);
+        }
+        Ok(())
+    }
+}
+
+fn known_table(table_name: &str) -> Result<String, ScopeError> {
+    apply::synced_table_by_name(table_name)
+        .map(|table| table.table_name.to_string())
+        .ok_or_else(|| ScopeError::UnknownTable(table_name.to_string()))
+}
+
+/// One condition on a field of a row's `data` document.
+#[derive(Debug, Clone)]
+struct FieldFilter {
+    /// JSON path of the field, e.g. `$.job_address.city`.
+    path: String,
+    /// SQL comparison; `IS` / `IS NOT` so `null` compares like any other value.
+    operator: &'static str,
+    values: Vec<SqlValue>,
+}
+
+impl FieldFilter {
+    fn new(table_name: &str, field: &str, operator: &str, value: Value) -> Result<Self, ScopeError> {
+        let invalid = |message: &str| ScopeError::InvalidFilter(format!("filters.{}.{}: {}", table_name, field, message));
+        let scalar = |value: Value| sql_scalar(value).ok_or_else(|| invalid("values must be strings, numbers, booleans or null."));
+        let (operator, values) = match operator {
+            "eq" => ("IS", vec![scalar(value)?]),
+            "ne" => ("IS NOT", vec![scalar(value)?]),
+            "lt" => ("<", vec![scalar(value)?]),
+            "lte" => ("<=", vec![scalar(value)?]),
+            "gt" => (">", vec![scalar(value)?]),
+            "gte" => (">=", vec![scalar(value)?]),
+            "in" => match value {
+                Value::Array(values) => ("IN", values.into_iter().map(scalar).collect::<Result<_, _>>()?),
+                _ => return Err(invalid("in takes an array of values.")),
+            },
+            _ => return Err(invalid(&format!("unknown operator \"{}\".", operator))),
+        };
+        Ok(FieldFilter { path: format!("$.{}", field), operator, values })
+    }
+
+    /// The SQL condition, appending its parameters to `values` (numbered after the ones already there).
+    fn to_sql(&self, values: &mut Vec<SqlValue>) -> String {
+        values.push(self.path.clone().into());
+        let field = format!("json_extract(data, ?{})", values.len());
+        let mut placeholders = Vec::new();
+        for value in &self.values {
+            values.push(value.clone());
+            placeholders.push(format!("?{}", values.len()));
+        }
+        if self.operator == "IN" {
+            format!("{} IN ({})", field, placeholders.join(", "))
+        } else {
+            format!("{} {} {}", field, self.operator, placeholders.join(""))
+        }
+    }
+}
+
+// A JSON scalar as `json_extract` returns it (booleans become 0 / 1).
+fn sql_scalar(value: Value) -> Option<SqlValue> {
+    match value {
+        Value::Null => Some(SqlValue::Null),
+        Value::Bool(value) => Some(SqlValue::Integer(value as i64)),
+        Value::Number(number) => number.as_i64().map(SqlValue::Integer).or_else(|| number.as_f64().map(SqlValue::Real)),
+        Value::String(value) => Some(SqlValue::Text(value)),
+        Value::Array(_) | Value::Object(_) => None,
+    }
+}
+
+/// Reasons the `tables` / `filters` parameters of a pull are rejected.
+#[derive(Debug)]
+pub enum ScopeError {
+    /// The name isn't a synced table.
+    UnknownTable(String),
+    InvalidFilter(String),
+}
+
+impl ScopeError {
+    /// Stable error code returned to clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ScopeError::UnknownTable(_) => "unknown_table",
+            ScopeError::InvalidFilter(_) => "invalid_filter",
+        }
+    }
+}
+
+impl std::fmt::Display for ScopeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScopeError::UnknownTable(table_name) => write!(f, "\"{}\" is not a synced table.", table_name),
+            ScopeError::InvalidFilter(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Describes one synced domain table (one entry per `ResponseData` field).
+/// `object_name` is the value clients send in overlays and the server stores in `change_log`.
+pub struct SyncedTable {
+    pub object_name: &'static str,
+    pub table_name: &'static str,
+    pub has_status: bool,
+    pub has_object_type: bool,
+    /// Whether a `data` document decodes as the table's generated model, as pulls read it.
+    pub validate_data: fn(&str) -> serde_json::Result<()>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two tables shaped like the synced ones, as far as `fetch_all` reads them.
+    fn database(rows: &[(&str, &str, &str)]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        for table_name in ["customers", "jobs"] {
+            conn.execute(&format!("CREATE TABLE {} (id TEXT PRIMARY KEY, tenant_id TEXT NOT NULL, updated_at TEXT NOT NULL, data JSON NOT NULL)", table_name), [])
+                .unwrap();
+        }
+        for (table_name, id, updated_at) in rows {
+            conn.execute(&format!("INSERT INTO {} VALUES (?1, 't1', ?2, '{{}}')", table_name), params![id, updated_at]).unwrap();
+        }
+        conn
+    }
+
+    fn id(row: &rusqlite::Row) -> Result<String> {
+        row.get("id")
+    }
+
+    // One pull: every table in order, the way `get_data_result` reads them.
+    fn pull(conn: &Connection, page: &mut DataPage) -> Vec<String> {
+        ["customers", "jobs"].iter().flat_map(|table_name| fetch_all(conn, table_name, "t1", "", page, id).unwrap()).collect()
+    }
+
+    // Every row, following `next` from page to page.
+    fn pull_all(conn: &Connection, limit: usize) -> Vec<Vec<String>> {
+        let mut pages = Vec::new();
+        let mut after = None;
+        loop {
+            let mut page = DataPage::new(Some(limit), after);
+            pages.push(pull(conn, &mut page));
+            // Cursors travel to the client and back as text.
+            after = match page.next {
+                Some(next) => Some(TableCursor::parse(&next.encode()).unwrap()),
+                None => return pages,
+            };
+        }
+    }
+
+    #[test]
+    fn cursor_round_trips_through_text() {
+        let cursor = TableCursor { table_name: "jobs".to_string(), updated_at: "2024-05-01T10:00:00.000000001Z".to_string(), id: "a|b".to_string() };
+        let parsed = TableCursor::parse(&cursor.encode()).unwrap();
+        assert_eq!((parsed.table_name.as_str(), parsed.updated_at.as_str(), parsed.id.as_str()), ("jobs", "2024-05-01T10:00:00.000000001Z", "a|b"));
+    }
+
+    #[test]
+    fn cursor_needs_all_three_parts() {
+        assert!(TableCursor::parse("jobs").is_none());
+        assert!(TableCursor::parse("jobs|2024-05-01T10:00:00Z").is_none());
+        assert!(TableCursor::parse("jobs|2024-05-01T10:00:00Z|").is_some());
+    }
+
+    #[test]
+    fn pages_break_ties_on_updated_at_by_id() {
+        let conn = database(&[("jobs", "j2", "t"), ("jobs", "j1", "t"), ("jobs", "j3", "t")]);
+        assert_eq!(pull_all(&conn, 2), vec![vec!["j1", "j2"], vec!["j3"]]);
+    }
+
+    #[test]
+    fn page_ending_on_a_table_boundary_still_has_more() {
+        let conn = database(&[("customers", "c1", "1"), ("customers", "c2", "2"), ("jobs", "j1", "1")]);
+        let mut page = DataPage::new(Some(2), None);
+        assert_eq!(pull(&conn, &mut page), vec!["c1", "c2"]);
+        assert!(page.has_more());
+        assert_eq!(page.next.as_ref().unwrap().encode(), "jobs||");
+
+        assert_eq!(pull_all(&conn, 2), vec![vec!["c1", "c2"], vec!["j1"]]);
+    }
+
+    #[test]
+    fn page_ending_on_the_last_row_has_no_more() {
+        let conn = database(&[("customers", "c1", "1"), ("jobs", "j1", "1")]);
+        let mut page = DataPage::new(Some(2), None);
+        assert_eq!(pull(&conn, &mut page), vec!["c1", "j1"]);
+        assert!(!page.has_more());
+
+        // Trailing empty tables don't make a full page look unfinished.
+        let conn = database(&[("customers", "c1", "1"), ("customers", "c2", "2")]);
+        let mut page = DataPage::new(Some(2), None);
+        assert_eq!(pull(&conn, &mut page), vec!["c1", "c2"]);
+        assert!(!page.has_more());
+    }
+
+    #[test]
+    fn unlimited_reads_everything_in_one_page() {
+        let conn = database(&[("customers", "c1", "1"), ("jobs", "j1", "1"), ("jobs", "j2", "2")]);
+        let mut page = DataPage::unlimited();
+        assert_eq!(pull(&conn, &mut page), vec!["c1", "j1", "j2"]);
+        assert!(!page.has_more());
+    }
+
+    #[test]
+    fn pages_stop_at_the_watermark() {
+        let conn = database(&[("jobs", "j1", "1"), ("jobs", "j2", "2"), ("jobs", "j3", "3")]);
+        let mut page = DataPage::new(Some(5), None).up_to("2");
+        assert_eq!(pull(&conn, &mut page), vec!["j1", "j2"]);
+        assert!(!page.has_more());
+    }
 }
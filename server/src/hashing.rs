@@ -0,0 +1,370 @@
+// This file contains the hashes that build each tenant's change_log chain.
+
+use serde::{Serialize, Serializer};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::change_log::ChangeOperation;
+
+/// How a change's content hash was computed. Stored on every `change_log` row in
+/// `hash_version` so old chains stay verifiable after the algorithm changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashVersion {
+    /// `changes` serialized by serde_json (sorted keys, serde number formatting).
+    /// Rows written before `hash_version` existed use this.
+    #[default]
+    Legacy = 1,
+    /// `changes` serialized as RFC 8785 (JCS) canonical JSON.
+    Jcs = 2,
+    /// The whole preimage is one JCS array of the hashed fields, so no two
+    /// different field splits can produce the same bytes. Also covers `operation`.
+    JcsArray = 3,
+}
+
+/// Versions accepted from clients on `POST /sync`. The concatenated preimages (1 and 2)
+/// stay accepted while clients migrate to 3; stored rows of every version remain verifiable.
+pub const ACCEPTED_HASH_VERSIONS: &[HashVersion] = &[HashVersion::Legacy, HashVersion::Jcs, HashVersion::JcsArray];
+
+impl HashVersion {
+    pub fn as_i64(&self) -> i64 {
+        *self as i64
+    }
+
+    pub fn from_i64(value: i64) -> Option<Self> {
+        match value {
+            1 => Some(HashVersion::Legacy),
+            2 => Some(HashVersion::Jcs),
+            3 => Some(HashVersion::JcsArray),
+            _ => None,
+        }
+    }
+
+    /// Serialize `changes` exactly as this version hashes them.
+    pub fn changes_text(&self, changes: &Value) -> String {
+        match self {
+            HashVersion::Legacy => changes.to_string(),
+            HashVersion::Jcs | HashVersion::JcsArray => canonical_json(changes),
+        }
+    }
+}
+
+// Clients and API responses see the version as a plain number.
+impl Serialize for HashVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(self.as_i64())
+    }
+}
+
+/// The fields a change's content hash covers.
+pub struct ChangeHashInput<'a> {
+    pub id: &'a str,
+    pub tenant_id: &'a str,
+    pub user_id: &'a str,
+    pub created_at: &'a str,
+    pub object_name: &'a str,
+    pub record_id: &'a str,
+    pub operation: ChangeOperation,
+    pub changes: &'a Value,
+}
+
+/// Hash a change's content.
+/// - Versions 1 and 2: `sha256(id + tenant_id + user_id + created_at + object_name + record_id + changes)`.
+/// - Version 3: `sha256(jcs([id, tenant_id, user_id, created_at, object_name, record_id, operation, changes]))`.
+pub fn change_hash(version: HashVersion, input: &ChangeHashInput) -> String {
+    match version {
+        HashVersion::Legacy | HashVersion::Jcs => sha256_hex(&format!(
+            "{}{}{}{}{}{}{}",
+            input.id,
+            input.tenant_id,
+            input.user_id,
+            input.created_at,
+            input.object_name,
+            input.record_id,
+            version.changes_text(input.changes)
+        )),
+        HashVersion::JcsArray => sha256_hex(&canonical_json(&json!([
+            input.id,
+            input.tenant_id,
+            input.user_id,
+            input.created_at,
+            input.object_name,
+            input.record_id,
+            input.operation.as_str(),
+            input.changes,
+        ]))),
+    }
+}
+
+/// Combine a content hash with the previous state hash: `H_new = hash(hash(change) + H_previous)`.
+pub fn chain_state_hash(change_hash: &str, previous_state_hash: &str) -> String {
+    sha256_hex(&format!("{}{}", change_hash, previous_state_hash))
+}
+
+fn sha256_hex(data: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Serialize a JSON value with the JSON Canonicalization Scheme (RFC 8785):
+/// no whitespace, object keys sorted by UTF-16 code units, numbers in their
+/// shortest ECMAScript form and strings with only the mandatory escapes.
+pub fn canonical_json(value: &Value) -> String {
+    let mut out = String::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        // JCS numbers are IEEE 754 doubles; larger integers lose precision exactly as in JavaScript.
+        Value::Number(n) => out.push_str(&format_number(n.as_f64().unwrap_or(0.0))),
+        Value::String(s) => write_string(s, out),
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        Value::Object(fields) => {
+            let mut keys: Vec<&String> = fields.keys().collect();
+            keys.sort_by(|a, b| a.encode_utf16().cmp(b.encode_utf16()));
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(key, out);
+                out.push(':');
+                write_canonical(&fields[key], out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{08}' => out.push_str("\\b"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\u{0C}' => out.push_str("\\f"),
+            '\r' => out.push_str("\\r"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Format a finite double like ECMAScript's `Number.prototype.toString`.
+fn format_number(value: f64) -> String {
+    if value == 0.0 {
+        // Covers -0 as well.
+        return "0".to_string();
+    }
+    let sign = if value < 0.0 { "-" } else { "" };
+
+    let (digits, exponent) = shortest_digits(value.abs());
+
+    // In ECMAScript terms the value is 0.digits × 10^n.
+    let k = digits.len() as i32;
+    let n = exponent + 1;
+    let body = if k <= n && n <= 21 {
+        format!("{}{}", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{}", "0".repeat((-n) as usize), digits)
+    } else {
+        let exponent_sign = if n - 1 < 0 { "-" } else { "+" };
+        let fraction = if k > 1 { format!(".{}", &digits[1..]) } else { String::new() };
+        format!("{}{}e{}{}", &digits[..1], fraction, exponent_sign, (n - 1).abs())
+    };
+    format!("{}{}", sign, body)
+}
+
+// The shortest digits that round-trip to `value` and the decimal exponent of the first,
+// as in `d.ddd × 10^exponent`. `{:e}` picks the candidate closest to the value, as
+// ECMAScript does, but when the value lies exactly halfway between two candidates it
+// rounds up where ECMAScript picks the one with an even last digit.
+fn shortest_digits(value: f64) -> (String, i32) {
+    let (digits, exponent) = split_scientific(&format!("{:e}", value));
+    let k = digits.len();
+    // A tie needs an exact expansion of k + 1 digits ending in 5; rule most values out cheaply first.
+    if !split_scientific(&format!("{:.*e}", k, value)).0.ends_with('5') {
+        return (digits, exponent);
+    }
+    // Every double's exact decimal expansion fits in this many digits, so nothing is rounded.
+    let (exact, exact_exponent) = split_scientific(&format!("{:.1100e}", value));
+    let exact = exact.trim_end_matches('0');
+    if exact.len() == k + 1 && exact.ends_with('5') {
+        let down = exact[..k].trim_end_matches('0');
+        let even = matches!(down.bytes().last(), Some(b'0' | b'2' | b'4' | b'6' | b'8'));
+        let round_trips = format!("{}.{}e{}", &down[..1], &down[1..], exact_exponent).parse::<f64>() == Ok(value);
+        if even && round_trips {
+            return (down.to_string(), exact_exponent);
+        }
+    }
+    (digits, exponent)
+}
+
+// Split `d.ddde±x` into its digits and exponent.
+fn split_scientific(scientific: &str) -> (String, i32) {
+    let (mantissa, exponent) = scientific.split_once('e').expect("{:e} always has an exponent");
+    let digits = mantissa.chars().filter(|c| *c != '.').collect();
+    (digits, exponent.parse().expect("{:e} exponent is an integer"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn input(changes: &Value) -> ChangeHashInput<'_> {
+        ChangeHashInput {
+            id: "c1",
+            tenant_id: "t1",
+            user_id: "u1",
+            created_at: "2024-05-01T10:00:00Z",
+            object_name: "job",
+            record_id: "j1",
+            operation: ChangeOperation::Update,
+            changes,
+        }
+    }
+
+    #[test]
+    fn numbers_match_rfc_8785_appendix_b() {
+        let cases: &[(u64, &str)] = &[
+            (0x0000000000000000, "0"),
+            (0x8000000000000000, "0"),
+            (0x0000000000000001, "5e-324"),
+            (0x8000000000000001, "-5e-324"),
+            (0x7fefffffffffffff, "1.7976931348623157e+308"),
+            (0xffefffffffffffff, "-1.7976931348623157e+308"),
+            (0x4340000000000000, "9007199254740992"),
+            (0xc340000000000000, "-9007199254740992"),
+            (0x4430000000000000, "295147905179352830000"),
+            (0x44b52d02c7e14af5, "9.999999999999997e+22"),
+            (0x44b52d02c7e14af6, "1e+23"),
+            (0x44b52d02c7e14af7, "1.0000000000000001e+23"),
+            (0x444b1ae4d6e2ef4e, "999999999999999700000"),
+            (0x444b1ae4d6e2ef4f, "999999999999999900000"),
+            (0x444b1ae4d6e2ef50, "1e+21"),
+            (0x3eb0c6f7a0b5ed8c, "9.999999999999997e-7"),
+            (0x3eb0c6f7a0b5ed8d, "0.000001"),
+            (0x41b3de4355555553, "333333333.3333332"),
+            (0x41b3de4355555554, "333333333.33333325"),
+            (0x41b3de4355555555, "333333333.3333333"),
+            (0x41b3de4355555556, "333333333.3333334"),
+            (0x41b3de4355555557, "333333333.33333343"),
+            (0xbecbf647612f3696, "-0.0000033333333333333333"),
+            (0x43143ff3c1cb0959, "1424953923781206.2"),
+        ];
+        for (bits, expected) in cases {
+            let value = f64::from_bits(*bits);
+            assert_eq!(canonical_json(&json!(value)), *expected, "formatting {:#018x}", bits);
+        }
+    }
+
+    #[test]
+    fn integers_and_plain_decimals_drop_trailing_zeros() {
+        assert_eq!(canonical_json(&json!([1, -7, 1.0, 4.50, 0.002, 100, 1e21, 1e-7])), "[1,-7,1,4.5,0.002,100,1e+21,1e-7]");
+    }
+
+    #[test]
+    fn keys_sort_by_utf16_code_units() {
+        // RFC 8785, section 3.2.3: U+1F600 is a surrogate pair (D83D DE00), so it sorts before U+FB33.
+        let value: Value = serde_json::from_str(
+            r#"{"€":"Euro Sign","\r":"Carriage Return","דּ":"Hebrew Letter Dalet With Dagesh","1":"One","😀":"Emoji: Grinning Face","\u0080":"Control","ö":"Latin Small Letter O With Diaeresis"}"#,
+        )
+        .unwrap();
+        let canonical = canonical_json(&value);
+        let keys: Vec<String> = canonical
+            .split(",\"")
+            .map(|field| field.trim_start_matches("{\"").split("\":").next().unwrap().to_string())
+            .collect();
+        assert_eq!(keys, ["\\r", "1", "\u{80}", "\u{f6}", "\u{20ac}", "\u{1f600}", "\u{fb33}"]);
+    }
+
+    #[test]
+    fn strings_use_only_the_mandatory_escapes() {
+        let value = json!("\u{20ac}$\u{0F}\nA'B\"\\\\\"/\u{08}\t\u{0C}\r\u{7F}");
+        assert_eq!(canonical_json(&value), "\"\u{20ac}$\\u000f\\nA'B\\\"\\\\\\\\\\\"/\\b\\t\\f\\r\u{7F}\"");
+    }
+
+    #[test]
+    #[allow(clippy::excessive_precision)] // The RFC's input is deliberately more precise than a double.
+    fn matches_the_rfc_8785_example() {
+        // RFC 8785, section 3.2.2.
+        let value = json!({
+            "numbers": [333333333.33333329, 1e30, 4.50, 2e-3, 0.000000000000000000000000001],
+            "string": "\u{20ac}$\u{0F}\nA'B\"\\\\\"/",
+            "literals": [null, true, false],
+        });
+        assert_eq!(
+            canonical_json(&value),
+            r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#
+        );
+    }
+
+    #[test]
+    fn nested_objects_are_sorted_without_whitespace() {
+        let value = json!({"b": {"z": 1, "a": [true, {"y": null, "x": "s"}]}, "a": []});
+        assert_eq!(canonical_json(&value), r#"{"a":[],"b":{"a":[true,{"x":"s","y":null}],"z":1}}"#);
+    }
+
+    #[test]
+    fn concatenated_versions_differ_only_in_how_changes_are_serialized() {
+        let changes = json!({"b": "x", "a": 1.0});
+        assert_eq!(HashVersion::Legacy.changes_text(&changes), r#"{"a":1.0,"b":"x"}"#);
+        assert_eq!(HashVersion::Jcs.changes_text(&changes), r#"{"a":1,"b":"x"}"#);
+        // sha256("c1t1u12024-05-01T10:00:00Zjobj1" + changes text)
+        assert_eq!(change_hash(HashVersion::Legacy, &input(&changes)), "77cf6c9942b4f337e96636cd98986971721c9db9a7dc1297a5f17ce0bada12ef");
+        assert_eq!(change_hash(HashVersion::Jcs, &input(&changes)), "402bf2932dced1c59a75d47968f4549a8346e4e2873feec546e95b408808b288");
+    }
+
+    #[test]
+    fn version_3_hashes_one_canonical_array_including_the_operation() {
+        let changes = json!({"b": "x", "a": 1.0});
+        // sha256(r#"["c1","t1","u1","2024-05-01T10:00:00Z","job","j1","update",{"a":1,"b":"x"}]"#)
+        assert_eq!(change_hash(HashVersion::JcsArray, &input(&changes)), "dba88087d42fbd1dd03250519cfa96a71f091455ab04a29b0b39e36dedc18d89");
+
+        // sha256(r#"["c1","t1","u1","2024-05-01T10:00:00Z","job","j1","delete",{}]"#)
+        let empty = json!({});
+        let delete = ChangeHashInput { operation: ChangeOperation::Delete, ..input(&empty) };
+        assert_eq!(change_hash(HashVersion::JcsArray, &delete), "b34620451be41ce7e6fdf7937c7ced74b28049743f72d91ae63543ebc478fc81");
+    }
+
+    #[test]
+    fn version_3_keeps_field_boundaries() {
+        // Moving characters between adjacent fields collides in the concatenated preimages but not in version 3.
+        let changes = json!({});
+        let shifted = ChangeHashInput { id: "c1t", tenant_id: "1", ..input(&changes) };
+        assert_eq!(change_hash(HashVersion::Jcs, &shifted), change_hash(HashVersion::Jcs, &input(&changes)));
+        assert_ne!(change_hash(HashVersion::JcsArray, &shifted), change_hash(HashVersion::JcsArray, &input(&changes)));
+    }
+
+    #[test]
+    fn state_hash_chains_the_change_hash_onto_the_previous_one() {
+        let changes = json!({"a": 1});
+        let change = change_hash(HashVersion::JcsArray, &input(&changes));
+        // sha256(change hash + 64 zeros)
+        assert_eq!(
+            chain_state_hash(&change, "0000000000000000000000000000000000000000000000000000000000000000"),
+            "ec474bdcb8a203f28f7e7b4dbd28cbe7818bf266c89116df3ec62c78ff387fa9"
+        );
+    }
+}
@@ -0,0 +1,143 @@
+// This file contains per-record history rebuilt from the change_log.
+
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::change_log::{self, ChangeOperation};
+use crate::compaction::{self, Snapshot};
+use crate::merge::ReplayedRecord;
+use crate::projection::ProjectedRecord;
+
+/// Every change to one record, oldest first, with the document after each of them.
+#[derive(Serialize, Debug)]
+pub struct RecordHistory {
+    pub tenant_id: String,
+    pub object_name: String,
+    pub record_id: String,
+    /// Set when the tenant's change_log was compacted: older changes are gone and
+    /// `steps` start from the record as the snapshot captured it.
+    pub compacted: Option<HistoryStart>,
+    pub steps: Vec<HistoryStep>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HistoryStart {
+    pub snapshot_id: String,
+    pub sequence_id: i64,
+    pub state: RecordState,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HistoryStep {
+    pub sequence_id: i64,
+    pub change_id: String,
+    /// Who made the change and when they made it.
+    pub user_id: String,
+    pub created_at: String,
+    pub operation: ChangeOperation,
+    /// The merge patch as it was submitted.
+    pub changes: Value,
+    pub state_hash: String,
+    /// The record right after this change.
+    pub state: RecordState,
+}
+
+/// A record reconstructed at one position of the tenant's chain.
+#[derive(Serialize, Debug)]
+pub struct PointInTimeRecord {
+    pub tenant_id: String,
+    pub object_name: String,
+    pub record_id: String,
+    /// The chain position the record was reconstructed at (`0` / `GENESIS_HASH` before any change).
+    pub sequence_id: i64,
+    pub state_hash: String,
+    pub state: RecordState,
+}
+
+/// A record as of one point of its history.
+#[derive(Serialize, Debug, Default)]
+pub struct RecordState {
+    /// `None` until the record exists (a delete of a record that was never created).
+    pub version: Option<i64>,
+    pub deleted: bool,
+    /// The full `data` document. Tombstones keep the document they were deleted with.
+    pub document: Option<Value>,
+}
+
+impl RecordState {
+    /// Describe a replayed record (`None`: it doesn't exist yet).
+    pub fn of(record: Option<&ProjectedRecord>) -> Self {
+        let Some(record) = record else {
+            return RecordState::default();
+        };
+        let (document, deleted) = match &record.state {
+            ReplayedRecord::Missing => (None, false),
+            ReplayedRecord::Live(document) => (Some(document.clone()), false),
+            ReplayedRecord::Deleted(document) => (Some(document.clone()), true),
+        };
+        RecordState { version: Some(record.version), deleted, document }
+    }
+}
+
+/// Replay one record's changes from the start of the tenant's chain. Returns `None`
+/// when the record has neither changes nor a snapshot entry.
+pub fn record_history(conn: &Connection, tenant_id: &str, object_name: &str, record_id: &str) -> Result<Option<RecordHistory>> {
+    let (snapshot, mut record) = replay_start(conn, tenant_id, object_name, record_id)?;
+    let changes = change_log::fetch_record_changes(conn, tenant_id, object_name, record_id, None)?;
+    if record.is_none() && changes.is_empty() {
+        return Ok(None);
+    }
+
+    let compacted = snapshot.filter(|_| record.is_some()).map(|snapshot| HistoryStart {
+        snapshot_id: snapshot.id,
+        sequence_id: snapshot.sequence_id,
+        state: RecordState::of(record.as_ref()),
+    });
+    let steps = changes
+        .into_iter()
+        .map(|change| {
+            ProjectedRecord::apply(&mut record, &change);
+            HistoryStep {
+                sequence_id: change.sequence_id,
+                change_id: change.id,
+                user_id: change.user_id,
+                created_at: change.created_at,
+                operation: change.operation,
+                changes: change.change_data,
+                state_hash: change.state_hash,
+                state: RecordState::of(record.as_ref()),
+            }
+        })
+        .collect();
+    Ok(Some(RecordHistory {
+        tenant_id: tenant_id.to_string(),
+        object_name: object_name.to_string(),
+        record_id: record_id.to_string(),
+        compacted,
+        steps,
+    }))
+}
+
+/// Rebuild a record as of `sequence_id` by replaying its changes up to that row.
+/// Returns `None` when that position precedes the latest compaction anchor.
+pub fn record_at(conn: &Connection, tenant_id: &str, object_name: &str, record_id: &str, sequence_id: i64) -> Result<Option<RecordState>> {
+    let (snapshot, mut record) = replay_start(conn, tenant_id, object_name, record_id)?;
+    if snapshot.is_some_and(|snapshot| sequence_id < snapshot.sequence_id) {
+        return Ok(None);
+    }
+    for change in change_log::fetch_record_changes(conn, tenant_id, object_name, record_id, Some(sequence_id))? {
+        ProjectedRecord::apply(&mut record, &change);
+    }
+    Ok(Some(RecordState::of(record.as_ref())))
+}
+
+// The tenant's latest snapshot and the record as it captured it; replays start there.
+fn replay_start(conn: &Connection, tenant_id: &str, object_name: &str, record_id: &str) -> Result<(Option<Snapshot>, Option<ProjectedRecord>)> {
+    let snapshot = compaction::latest_snapshot(conn, tenant_id)?;
+    let record = match &snapshot {
+        Some(snapshot) => compaction::snapshot_record(conn, &snapshot.id, object_name, record_id)?,
+        None => None,
+    };
+    Ok((snapshot, record))
+}
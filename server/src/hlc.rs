@@ -0,0 +1,151 @@
+// This file contains the hybrid logical clock that stamps accepted changes.
+//
+// Client `created_at` values come from phone clocks, which drift, so they can't order
+// changes or drive `since` filtering. Instead the server stamps every accepted change
+// with an HLC: its own wall-clock milliseconds plus a logical counter, so stamps never
+// go backwards for a tenant even when the server clock does or many changes land in
+// the same millisecond.
+//
+// A stamp is written as an RFC 3339 UTC timestamp with exactly nine fractional digits:
+// three for the milliseconds and six for the counter. Stamps are therefore valid
+// timestamps and, because the width is fixed, sort the same way as text and as time.
+//
+// Rows written before the HLC (or by the seeder) can carry any `updated_at`, including
+// ones ahead of the server clock. The clock starts past the latest of them, so every new
+// stamp sorts after every row a client may already have pulled.
+
+use chrono::{DateTime, Duration, DurationRound, NaiveDateTime, SecondsFormat, Utc};
+use rusqlite::{Connection, OptionalExtension, Result, params};
+
+use crate::fetching;
+use crate::routes::data_result::SYNCED_TABLES;
+
+/// Format a timestamp the way stamps are stored, so it compares correctly with them as text.
+pub fn format(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+/// Parse any RFC 3339 timestamp (e.g. a client's `since`) into the stored stamp format.
+pub fn normalize(timestamp: &str) -> Option<String> {
+    DateTime::parse_from_rfc3339(timestamp).ok().map(|timestamp| format(timestamp.with_timezone(&Utc)))
+}
+
+/// Parse a timestamp as stored in a database column: RFC 3339, or the
+/// `YYYY-MM-DD HH:MM:SS` UTC text of SQLite's `CURRENT_TIMESTAMP`.
+pub fn parse_stored(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S%.f").map(|timestamp| timestamp.and_utc()))
+        .ok()
+}
+
+/// The stamp after `last`: the current millisecond when the wall clock has moved past
+/// it, otherwise `last` with its counter bumped. Counters start at 1, so a stamp is
+/// always later than a wall-clock reading of the same millisecond (see `format(Utc::now())`).
+pub fn tick(last: Option<DateTime<Utc>>, now: DateTime<Utc>) -> DateTime<Utc> {
+    let now = now.duration_trunc(Duration::milliseconds(1)).unwrap_or(now);
+    let counter = Duration::nanoseconds(1);
+    match last {
+        // A counter that outgrows its six digits carries into the milliseconds, running ahead of the wall clock.
+        Some(last) if last >= now => last + counter,
+        _ => now + counter,
+    }
+}
+
+/// Stamp a change for `tenant_id`. Call it in the transaction that appends the change,
+/// so the tenant's last stamp can't move in between.
+///
+/// The clock continues from the tenant's last stamp or its latest domain `updated_at`,
+/// whichever is later, so the new row also sorts after legacy rows stamped in the future
+/// and `fetching::watermark` never runs ahead of it. Both reads are index seeks (see
+/// `migrations::migrate`), so this stays cheap as the tables grow.
+pub fn next_stamp(conn: &Connection, tenant_id: &str) -> Result<String> {
+    let last_stamp: Option<String> = conn
+        .query_row(
+            "SELECT hlc FROM change_log WHERE tenant_id = ?1 AND hlc IS NOT NULL ORDER BY sequence_id DESC LIMIT 1",
+            params![tenant_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let last_update = fetching::latest_update(conn, tenant_id, SYNCED_TABLES)?;
+    let last = [last_stamp, last_update].iter().flatten().filter_map(|last| parse_stored(last)).max();
+    Ok(format(tick(last, Utc::now())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        parse_stored(timestamp).unwrap()
+    }
+
+    // The change_log and every synced table, as far as `next_stamp` reads them.
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE change_log (sequence_id INTEGER PRIMARY KEY, tenant_id TEXT NOT NULL, hlc TEXT)", []).unwrap();
+        for table in SYNCED_TABLES {
+            conn.execute(&format!("CREATE TABLE {} (id TEXT PRIMARY KEY, tenant_id TEXT NOT NULL, updated_at TEXT NOT NULL)", table.table_name), []).unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn first_stamp_of_a_millisecond_is_after_the_wall_clock() {
+        let now = at("2024-05-01T10:00:00.123456Z");
+        assert_eq!(format(tick(None, now)), "2024-05-01T10:00:00.123000001Z");
+        assert!(format(tick(None, now)) > format(now.duration_trunc(Duration::milliseconds(1)).unwrap()));
+    }
+
+    #[test]
+    fn counter_bumps_within_a_millisecond() {
+        let now = at("2024-05-01T10:00:00.123Z");
+        let first = tick(None, now);
+        let second = tick(Some(first), now);
+        assert_eq!(format(second), "2024-05-01T10:00:00.123000002Z");
+    }
+
+    #[test]
+    fn wall_clock_resets_the_counter_once_it_moves_on() {
+        let last = at("2024-05-01T10:00:00.123000042Z");
+        assert_eq!(format(tick(Some(last), at("2024-05-01T10:00:00.124Z"))), "2024-05-01T10:00:00.124000001Z");
+    }
+
+    #[test]
+    fn full_counter_carries_into_the_milliseconds() {
+        let last = at("2024-05-01T10:00:00.123999999Z");
+        let next = format(tick(Some(last), at("2024-05-01T10:00:00.123Z")));
+        assert_eq!(next, "2024-05-01T10:00:00.124000000Z");
+        assert!(next > format(last));
+    }
+
+    #[test]
+    fn stamps_never_go_backwards_with_the_wall_clock() {
+        let last = at("2024-05-01T10:00:05.000000003Z");
+        let next = tick(Some(last), at("2024-05-01T10:00:00Z"));
+        assert_eq!(format(next), "2024-05-01T10:00:05.000000004Z");
+    }
+
+    #[test]
+    fn stored_timestamps_parse_in_either_format() {
+        assert_eq!(parse_stored("2024-05-01 10:00:00"), parse_stored("2024-05-01T10:00:00Z"));
+        assert_eq!(parse_stored("2024-05-01T12:00:00+02:00"), parse_stored("2024-05-01T10:00:00Z"));
+        assert_eq!(parse_stored("yesterday"), None);
+    }
+
+    #[test]
+    fn next_stamp_follows_the_tenants_last_stamp() {
+        let conn = database();
+        conn.execute("INSERT INTO change_log (tenant_id, hlc) VALUES ('t1', '2999-01-01T00:00:00.000000007Z')", []).unwrap();
+        assert_eq!(next_stamp(&conn, "t1").unwrap(), "2999-01-01T00:00:00.000000008Z");
+        assert!(next_stamp(&conn, "t2").unwrap().as_str() < "2999");
+    }
+
+    #[test]
+    fn next_stamp_passes_legacy_rows_stamped_in_the_future() {
+        let conn = database();
+        conn.execute("INSERT INTO change_log (tenant_id, hlc) VALUES ('t1', '2024-05-01T10:00:00.000000001Z')", []).unwrap();
+        conn.execute("INSERT INTO jobs VALUES ('j1', 't1', '2999-01-01 00:00:00')", []).unwrap();
+        assert_eq!(next_stamp(&conn, "t1").unwrap(), "2999-01-01T00:00:00.000000001Z");
+    }
+}
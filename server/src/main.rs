@@ -7,19 +7,94 @@ use serde_json::json;
 use rusqlite::Connection;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub mod models;
+pub mod migrations;
 pub mod fetching;
+pub mod change_log;
+pub mod hashing;
+pub mod hlc;
+pub mod schema_version;
+pub mod apply;
+pub mod merge;
+pub mod broadcast;
+pub mod verify;
+pub mod checkpoint;
+pub mod compaction;
+pub mod projection;
+pub mod history;
+pub mod revert;
+pub mod profiles;
+mod cli;
 mod routes;
-use routes::sync::{sync_handler, post_sync_handler, sync_handler_v2, AppState};
+use broadcast::ChangeBroadcaster;
+use checkpoint::CheckpointSigner;
+use routes::sync::{sync_handler, post_sync_handler, sync_handler_v2, bootstrap_handler, AppState};
+use routes::ws::ws_handler;
+use routes::chain::{verify_chain_handler, latest_checkpoint_handler};
+use routes::records::{record_history_handler, record_at_handler, revert_change_handler};
+
+const DB_PATH: &str = "/app/data/fieldprime.db";
+// Ed25519 secret key used to sign chain checkpoints; generated on first start.
+const SIGNING_KEY_PATH: &str = "/app/data/checkpoint_signing.key";
+// How often tenants whose chain head moved get a new signed checkpoint.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+// How often change_log rows older than the retention window are compacted.
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
 
 #[tokio::main]
 async fn main() {
+    // Bring a database created by an earlier release up to date before anything reads it.
+    if let Err(e) = migrations::migrate(DB_PATH) {
+        eprintln!("Failed to migrate the database: {}", e);
+        std::process::exit(2);
+    }
+
+    // Offline subcommands (e.g. `verify-chain`) run against the same database and exit.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = cli::run(&args, DB_PATH, SIGNING_KEY_PATH) {
+        std::process::exit(code);
+    }
+
     println!("Starting FieldPrime (Axum) server on port 8080...");
 
     // Shared SQLite connection state
-    let conn = Connection::open("/app/data/fieldprime.db").expect("Failed to open SQLite DB");
-    let state = AppState { db: Arc::new(Mutex::new(conn)) };
+    let conn = Connection::open(DB_PATH).expect("Failed to open SQLite DB");
+    let signer = CheckpointSigner::load_or_create(std::path::Path::new(SIGNING_KEY_PATH)).expect("Failed to load checkpoint signing key");
+    println!("Checkpoint public key: {}", signer.public_key());
+    let state = AppState { db: Arc::new(Mutex::new(conn)), broadcaster: ChangeBroadcaster::new(), signer };
+
+    // Periodically sign the head of every tenant whose chain moved.
+    let checkpoint_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECKPOINT_INTERVAL);
+        loop {
+            interval.tick().await;
+            let conn = checkpoint_state.db.lock().unwrap();
+            if let Err(e) = checkpoint::issue_due_checkpoints(&conn, &checkpoint_state.signer) {
+                eprintln!("Failed to issue checkpoints: {}", e);
+            }
+        }
+    });
+
+    // Periodically fold old change_log rows into per-tenant snapshots.
+    let compaction_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(COMPACTION_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut conn = compaction_state.db.lock().unwrap();
+            match compaction::compact_all(&mut conn, compaction::DEFAULT_RETENTION_DAYS) {
+                Ok(snapshots) => {
+                    for snapshot in snapshots {
+                        println!("Compacted {} change_log rows of tenant {} into snapshot {}", snapshot.pruned_rows, snapshot.tenant_id, snapshot.id);
+                    }
+                }
+                Err(e) => eprintln!("Failed to compact change_log: {}", e),
+            }
+        }
+    });
 
     // Build router with shared state
     let app = Router::new()
@@ -27,6 +102,13 @@ async fn main() {
         .route("/sync", get(sync_handler))
         .route("/sync", post(post_sync_handler))
         .route("/sync/v2", get(sync_handler_v2))
+        .route("/sync/bootstrap", get(bootstrap_handler))
+        .route("/ws", get(ws_handler))
+        .route("/tenants/:tenant_id/chain/verify", get(verify_chain_handler))
+        .route("/tenants/:tenant_id/checkpoints/latest", get(latest_checkpoint_handler))
+        .route("/records/:object_name/:id/history", get(record_history_handler))
+        .route("/records/:object_name/:id/at", get(record_at_handler))
+        .route("/changes/:change_id/revert", post(revert_change_handler))
         .with_state(state);
 
     // Start server
@@ -53,7 +135,7 @@ async fn health() -> impl IntoResponse {
 }
 
 fn check_db() -> Result<bool, rusqlite::Error> {
-    let conn = Connection::open("/app/data/fieldprime.db")?;
+    let conn = Connection::open(DB_PATH)?;
     let _: i64 = conn.query_row("select count(*) from tenants;", [], |row| row.get(0))?;
     Ok(true)
 }
\ No newline at end of file
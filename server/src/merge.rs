@@ -0,0 +1,325 @@
+// This file contains field-level reasoning about JSON merge patches.
+
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::change_log::{ChangeLogRecord, ChangeOperation};
+
+/// The top-level `data` fields a change touches, or `None` when it touches the
+/// whole record (deletes, and patches that aren't JSON objects).
+pub fn touched_fields(operation: ChangeOperation, change_data: &Value) -> Option<BTreeSet<String>> {
+    if operation == ChangeOperation::Delete {
+        return None;
+    }
+    change_data.as_object().map(|fields| fields.keys().cloned().collect())
+}
+
+/// The fields two changes to the same record both touch. `None` means they don't
+/// overlap; `Some(empty set)` means one of them touches the whole record.
+pub fn overlapping_fields(
+    operation: ChangeOperation,
+    change_data: &Value,
+    other: &ChangeLogRecord,
+) -> Option<BTreeSet<String>> {
+    match (touched_fields(operation, change_data), touched_fields(other.operation, &other.change_data)) {
+        (Some(mine), Some(theirs)) => {
+            let overlap: BTreeSet<String> = mine.intersection(&theirs).cloned().collect();
+            if overlap.is_empty() { None } else { Some(overlap) }
+        }
+        _ => Some(BTreeSet::new()),
+    }
+}
+
+/// Apply an RFC 7386 JSON merge patch in place, matching SQLite's `json_patch`.
+pub fn apply_merge_patch(target: &mut Value, patch: &Value) {
+    let Some(patch_fields) = patch.as_object() else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let target_fields = target.as_object_mut().expect("target was just made an object");
+    for (key, value) in patch_fields {
+        if value.is_null() {
+            target_fields.remove(key);
+        } else {
+            apply_merge_patch(target_fields.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// The merge patch that turns `from` into `to`, so `apply_merge_patch(from, diff_patch(from, to)) == to`.
+/// Fields that are `null` in `to` come out removed: a merge patch can't set a value to `null`.
+pub fn diff_patch(from: &Value, to: &Value) -> Value {
+    let (Some(from_fields), Some(to_fields)) = (from.as_object(), to.as_object()) else {
+        return to.clone();
+    };
+    let mut patch = serde_json::Map::new();
+    for key in from_fields.keys().filter(|key| !to_fields.contains_key(*key)) {
+        patch.insert(key.clone(), Value::Null);
+    }
+    for (key, to_value) in to_fields {
+        match from_fields.get(key) {
+            Some(from_value) if from_value == to_value => {}
+            Some(from_value) if from_value.is_object() && to_value.is_object() => {
+                patch.insert(key.clone(), diff_patch(from_value, to_value));
+            }
+            _ => {
+                patch.insert(key.clone(), to_value.clone());
+            }
+        }
+    }
+    Value::Object(patch)
+}
+
+/// A record's state while replaying its changes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ReplayedRecord {
+    /// Never created (or not yet, at this point of the chain).
+    #[default]
+    Missing,
+    Live(Value),
+    /// Soft-deleted, keeping the `data` the tombstone row still holds. Like the domain
+    /// tables, a delete wins: later updates are merged but don't bring the record back.
+    Deleted(Value),
+}
+
+impl ReplayedRecord {
+    /// Apply one change to the record, matching `apply::apply_change`.
+    pub fn apply(&mut self, change: &ChangeLogRecord) {
+        match (change.operation, &mut *self) {
+            (ChangeOperation::Delete, ReplayedRecord::Live(document)) => {
+                *self = ReplayedRecord::Deleted(std::mem::take(document));
+            }
+            // Deleting a record that was never synced (or again) is a no-op.
+            (ChangeOperation::Delete, ReplayedRecord::Missing | ReplayedRecord::Deleted(_)) => {}
+            (_, ReplayedRecord::Live(document) | ReplayedRecord::Deleted(document)) => {
+                apply_merge_patch(document, &change.change_data)
+            }
+            // A new row stores the change's data as-is.
+            (_, ReplayedRecord::Missing) => *self = ReplayedRecord::Live(change.change_data.clone()),
+        }
+    }
+
+    /// The `data` document, or `None` when the record doesn't exist or is deleted.
+    pub fn document(&self) -> Option<&Value> {
+        match self {
+            ReplayedRecord::Live(document) => Some(document),
+            ReplayedRecord::Missing | ReplayedRecord::Deleted(_) => None,
+        }
+    }
+}
+
+/// Replay a record's changes in order, starting from `start`.
+pub fn replay<'a>(start: ReplayedRecord, changes: impl IntoIterator<Item = &'a ChangeLogRecord>) -> ReplayedRecord {
+    changes.into_iter().fold(start, |mut record, change| {
+        record.apply(change);
+        record
+    })
+}
+
+/// One field that both the client and a change it hadn't seen modified.
+/// `field` is `"*"` when one side touches the whole record (e.g. a delete).
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldConflict {
+    pub field: String,
+    /// The value when the client made its edit (as of its anchor).
+    pub base: Value,
+    /// The server's current value.
+    pub theirs: Value,
+    /// The value the client's overlay sets.
+    pub mine: Value,
+    /// The unseen change that modified the field.
+    pub change_id: String,
+    pub sequence_id: i64,
+}
+
+/// Compare a client's change with the changes to the same record it hadn't seen.
+/// Disjoint fields merge cleanly; fields both sides set, to different values, are
+/// returned as conflicts. Setting the same value on both sides is not a conflict.
+pub fn field_conflicts<'a>(
+    operation: ChangeOperation,
+    change_data: &Value,
+    unseen: impl IntoIterator<Item = &'a ChangeLogRecord>,
+    base: Option<&Value>,
+    theirs: Option<&Value>,
+) -> Vec<FieldConflict> {
+    let field_of = |document: Option<&Value>, field: &str| document.and_then(|d| d.get(field)).cloned().unwrap_or(Value::Null);
+    let mine_whole = if operation == ChangeOperation::Delete { Value::Null } else { change_data.clone() };
+
+    // Keyed by field so a field touched by several unseen changes is reported once, for the latest.
+    let mut conflicts: std::collections::BTreeMap<String, FieldConflict> = Default::default();
+    for other in unseen {
+        let Some(fields) = overlapping_fields(operation, change_data, other) else {
+            continue;
+        };
+        if fields.is_empty() {
+            // Two deletes agree with each other.
+            if operation == ChangeOperation::Delete && other.operation == ChangeOperation::Delete {
+                continue;
+            }
+            conflicts.insert("*".to_string(), FieldConflict {
+                field: "*".to_string(),
+                base: base.cloned().unwrap_or(Value::Null),
+                theirs: theirs.cloned().unwrap_or(Value::Null),
+                mine: mine_whole.clone(),
+                change_id: other.id.clone(),
+                sequence_id: other.sequence_id,
+            });
+            continue;
+        }
+        for field in fields {
+            let mine = change_data.get(&field).cloned().unwrap_or(Value::Null);
+            let theirs_value = field_of(theirs, &field);
+            if mine == theirs_value {
+                continue;
+            }
+            conflicts.insert(field.clone(), FieldConflict {
+                base: field_of(base, &field),
+                theirs: theirs_value,
+                mine,
+                change_id: other.id.clone(),
+                sequence_id: other.sequence_id,
+                field,
+            });
+        }
+    }
+    conflicts.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::hashing::HashVersion;
+
+    fn change(sequence_id: i64, operation: ChangeOperation, change_data: Value) -> ChangeLogRecord {
+        ChangeLogRecord {
+            sequence_id,
+            id: format!("change-{}", sequence_id),
+            tenant_id: "t1".to_string(),
+            user_id: "u1".to_string(),
+            object_name: "job".to_string(),
+            record_id: "j1".to_string(),
+            operation,
+            change_data,
+            state_hash: String::new(),
+            previous_state_hash: String::new(),
+            created_at: "2024-05-01T10:00:00Z".to_string(),
+            hash_version: HashVersion::JcsArray,
+            hlc: None,
+            schema_version: None,
+        }
+    }
+
+    #[test]
+    fn merge_patch_matches_rfc_7386_examples() {
+        // RFC 7386, Appendix A.
+        let cases = [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "b"}), json!({"b": "c"}), json!({"a": "b", "b": "c"})),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (json!({"a": "b", "b": "c"}), json!({"a": null}), json!({"b": "c"})),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (json!({"a": {"b": "c"}}), json!({"a": {"b": "d", "c": null}}), json!({"a": {"b": "d"}})),
+            (json!({"a": [{"b": "c"}]}), json!({"a": [1]}), json!({"a": [1]})),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (json!({"a": "foo"}), json!("bar"), json!("bar")),
+            (json!({"e": null}), json!({"a": 1}), json!({"e": null, "a": 1})),
+            (json!([1, 2]), json!({"a": "b", "c": null}), json!({"a": "b"})),
+            (json!({}), json!({"a": {"bb": {"ccc": null}}}), json!({"a": {"bb": {}}})),
+        ];
+        for (target, patch, expected) in cases {
+            let mut result = target.clone();
+            apply_merge_patch(&mut result, &patch);
+            assert_eq!(result, expected, "patching {} with {}", target, patch);
+        }
+    }
+
+    #[test]
+    fn diff_patch_round_trips_nested_documents() {
+        let from = json!({"title": "Fix boiler", "address": {"city": "Leeds", "zip": "LS1"}, "tags": ["a"], "note": "x"});
+        let to = json!({"title": "Fix boiler", "address": {"city": "York", "zip": "LS1"}, "tags": ["a", "b"], "priority": 2});
+        let patch = diff_patch(&from, &to);
+        assert_eq!(patch, json!({"address": {"city": "York"}, "tags": ["a", "b"], "note": null, "priority": 2}));
+
+        let mut patched = from.clone();
+        apply_merge_patch(&mut patched, &patch);
+        assert_eq!(patched, to);
+    }
+
+    #[test]
+    fn diff_patch_removes_fields_that_become_null() {
+        let patch = diff_patch(&json!({"a": 1, "b": {"c": 2}}), &json!({"a": null, "b": {"c": null}}));
+        assert_eq!(patch, json!({"a": null, "b": {"c": null}}));
+
+        let mut patched = json!({"a": 1, "b": {"c": 2}});
+        apply_merge_patch(&mut patched, &patch);
+        assert_eq!(patched, json!({"b": {}}));
+    }
+
+    #[test]
+    fn diff_patch_replaces_non_objects_whole() {
+        assert_eq!(diff_patch(&json!({"a": 1}), &json!([1])), json!([1]));
+        assert_eq!(diff_patch(&json!("x"), &json!({"a": 1})), json!({"a": 1}));
+        assert_eq!(diff_patch(&json!({"a": 1}), &json!({"a": 1})), json!({}));
+    }
+
+    #[test]
+    fn disjoint_fields_do_not_conflict() {
+        let unseen = change(2, ChangeOperation::Update, json!({"status_note": "on site"}));
+        let conflicts = field_conflicts(
+            ChangeOperation::Update,
+            &json!({"job_description": "new"}),
+            [&unseen],
+            Some(&json!({"job_description": "old"})),
+            Some(&json!({"job_description": "old", "status_note": "on site"})),
+        );
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn same_field_conflicts_unless_set_to_the_same_value() {
+        let unseen = change(2, ChangeOperation::Update, json!({"job_description": "theirs"}));
+        let base = json!({"job_description": "base"});
+        let theirs = json!({"job_description": "theirs"});
+
+        let conflicts = field_conflicts(ChangeOperation::Update, &json!({"job_description": "mine"}), [&unseen], Some(&base), Some(&theirs));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].field, "job_description");
+        assert_eq!((&conflicts[0].base, &conflicts[0].theirs, &conflicts[0].mine), (&json!("base"), &json!("theirs"), &json!("mine")));
+        assert_eq!(conflicts[0].sequence_id, 2);
+
+        let agreeing = field_conflicts(ChangeOperation::Update, &json!({"job_description": "theirs"}), [&unseen], Some(&base), Some(&theirs));
+        assert!(agreeing.is_empty());
+    }
+
+    #[test]
+    fn deletes_conflict_with_every_edit_but_not_each_other() {
+        let delete = change(2, ChangeOperation::Delete, json!({}));
+        let conflicts = field_conflicts(ChangeOperation::Update, &json!({"a": 1}), [&delete], None, None);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].field, "*");
+
+        assert!(field_conflicts(ChangeOperation::Delete, &json!({}), [&delete], None, None).is_empty());
+    }
+
+    #[test]
+    fn replay_keeps_tombstones_deleted() {
+        let changes = [
+            change(1, ChangeOperation::Create, json!({"a": 1})),
+            change(2, ChangeOperation::Delete, json!({})),
+            change(3, ChangeOperation::Update, json!({"b": 2})),
+        ];
+        let record = replay(ReplayedRecord::Missing, &changes);
+        assert_eq!(record, ReplayedRecord::Deleted(json!({"a": 1, "b": 2})));
+        assert_eq!(record.document(), None);
+    }
+}
@@ -0,0 +1,193 @@
+// This file contains the startup migrations that bring an existing database up to the
+// schema this server expects.
+//
+// Databases are created by the schema codegen seeder from plan/specs/schema.ts, so a
+// database deployed before a column or server-only table was added doesn't have it and
+// every query that names it fails. On start the server adds whatever is missing and
+// backfills the new columns of existing rows. Every step checks the schema first, so
+// running the migrations again changes nothing.
+//
+// Domain rows written outside the server (the seeder, older servers) also get their
+// `updated_at` rewritten in the HLC stamp format, which pulls compare as text.
+
+use rusqlite::{Connection, Result, params};
+
+use crate::hashing::HashVersion;
+use crate::hlc;
+use crate::routes::data_result::SYNCED_TABLES;
+use crate::schema_version::INITIAL_SCHEMA_VERSION;
+
+// Matches `updated_at` values already in the stamp format `hlc::format` writes.
+const STAMP_GLOB: &str = "[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]T[0-9][0-9]:[0-9][0-9]:[0-9][0-9].[0-9][0-9][0-9][0-9][0-9][0-9][0-9][0-9][0-9]Z";
+
+// A column added after the table shipped, and what it holds on rows written before.
+struct AddedColumn {
+    table: &'static str,
+    column: &'static str,
+    definition: &'static str,
+    backfill: Backfill,
+}
+
+enum Backfill {
+    None,
+    Text(&'static str),
+    Integer(i64),
+    // `hlc` stamps need RFC 3339 parsing, which SQLite can't do.
+    HlcFromCreatedAt,
+}
+
+const ADDED_COLUMNS: &[AddedColumn] = &[
+    AddedColumn {
+        table: "change_log",
+        column: "operation",
+        definition: "TEXT",
+        // Rows written before operations were tracked are merge patches.
+        backfill: Backfill::Text("update"),
+    },
+    AddedColumn {
+        table: "change_log",
+        column: "hash_version",
+        definition: "INTEGER",
+        backfill: Backfill::Integer(HashVersion::Legacy as i64),
+    },
+    AddedColumn { table: "change_log", column: "hlc", definition: "TEXT", backfill: Backfill::HlcFromCreatedAt },
+    AddedColumn {
+        table: "change_log",
+        column: "schema_version",
+        definition: "INTEGER",
+        backfill: Backfill::Integer(INITIAL_SCHEMA_VERSION),
+    },
+    AddedColumn { table: "tenants", column: "schema_version", definition: "INTEGER NOT NULL DEFAULT 1", backfill: Backfill::None },
+];
+
+// Server-only tables, as the codegen generates them from schema.ts.
+const ADDED_TABLES: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS chain_checkpoints (
+        id INTEGER PRIMARY KEY NOT NULL,
+        tenant_id TEXT NOT NULL REFERENCES tenants(id),
+        sequence_id INTEGER NOT NULL,
+        state_hash TEXT NOT NULL,
+        timestamp TEXT NOT NULL,
+        public_key TEXT NOT NULL,
+        signature TEXT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS change_log_snapshots (
+        id TEXT PRIMARY KEY NOT NULL,
+        tenant_id TEXT NOT NULL REFERENCES tenants(id),
+        sequence_id INTEGER NOT NULL,
+        state_hash TEXT NOT NULL,
+        created_at TEXT NOT NULL DEFAULT (CURRENT_TIMESTAMP),
+        pruned_rows INTEGER NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS change_log_snapshot_entries (
+        snapshot_id TEXT NOT NULL REFERENCES change_log_snapshots(id),
+        object_name TEXT NOT NULL,
+        record_id TEXT NOT NULL,
+        data JSON NOT NULL,
+        deleted INTEGER NOT NULL,
+        version INTEGER NOT NULL DEFAULT 0,
+        created_by TEXT NOT NULL REFERENCES users(id),
+        created_at TEXT NOT NULL DEFAULT (CURRENT_TIMESTAMP),
+        modified_by TEXT NOT NULL REFERENCES users(id),
+        updated_at TEXT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS security_profiles (
+        id TEXT PRIMARY KEY NOT NULL,
+        tenant_id TEXT NOT NULL REFERENCES tenants(id),
+        name TEXT NOT NULL,
+        definition JSON NOT NULL,
+        created_at TEXT NOT NULL DEFAULT (CURRENT_TIMESTAMP),
+        updated_at TEXT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS security_profile_assignments (
+        user_id TEXT NOT NULL UNIQUE REFERENCES users(id),
+        tenant_id TEXT NOT NULL REFERENCES tenants(id),
+        profile_id TEXT NOT NULL REFERENCES security_profiles(id),
+        assigned_at TEXT NOT NULL
+    )",
+];
+
+// Constraints and lookups added to server-only tables after they shipped.
+const ADDED_INDEXES: &[&str] = &[
+    // Assignments are upserted on `user_id`; tables created before it was `@unique` lack the constraint.
+    "CREATE UNIQUE INDEX IF NOT EXISTS security_profile_assignments_user_id ON security_profile_assignments (user_id)",
+    // `hlc::next_stamp` reads the tenant's last change for every change it stamps.
+    "CREATE INDEX IF NOT EXISTS change_log_tenant_id_sequence_id ON change_log (tenant_id, sequence_id)",
+];
+
+/// Add every missing column, table and index to the database at `db_path`, in one transaction.
+pub fn migrate(db_path: &str) -> Result<()> {
+    let mut conn = Connection::open(db_path)?;
+    let tx = conn.transaction()?;
+    for added in ADDED_COLUMNS {
+        if has_column(&tx, added.table, added.column)? {
+            continue;
+        }
+        tx.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", added.table, added.column, added.definition), [])?;
+        let backfill = format!("UPDATE {} SET {} = ?1", added.table, added.column);
+        match added.backfill {
+            Backfill::None => {}
+            Backfill::Text(value) => {
+                tx.execute(&backfill, params![value])?;
+            }
+            Backfill::Integer(value) => {
+                tx.execute(&backfill, params![value])?;
+            }
+            Backfill::HlcFromCreatedAt => backfill_hlc(&tx)?,
+        }
+    }
+    for statement in ADDED_TABLES.iter().chain(ADDED_INDEXES) {
+        tx.execute(statement, [])?;
+    }
+    for table in SYNCED_TABLES {
+        normalize_updated_at(&tx, table.table_name)?;
+        // `fetching::latest_update` runs for every stamped change; this makes its MAX a single seek.
+        tx.execute(
+            &format!("CREATE INDEX IF NOT EXISTS {0}_tenant_id_updated_at ON {0} (tenant_id, updated_at)", table.table_name),
+            [],
+        )?;
+    }
+    tx.commit()
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+        params![table, column],
+        |row| row.get(0),
+    )
+}
+
+// Stamp rows accepted before the HLC with their `created_at`, the time they used to sync by.
+// Timestamps that don't parse stay NULL; `ChangeLogRecord::stamped_at` falls back to `created_at` for them.
+fn backfill_hlc(conn: &Connection) -> Result<()> {
+    let rows: Vec<(i64, String)> = {
+        let mut stmt = conn.prepare("SELECT sequence_id, created_at FROM change_log WHERE hlc IS NULL")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<Result<_>>()?
+    };
+    let mut update = conn.prepare("UPDATE change_log SET hlc = ?1 WHERE sequence_id = ?2")?;
+    for (sequence_id, created_at) in rows {
+        if let Some(stamp) = hlc::parse_stored(&created_at) {
+            update.execute(params![hlc::format(stamp), sequence_id])?;
+        }
+    }
+    Ok(())
+}
+
+// Rewrite `updated_at` values not yet in the stamp format (e.g. the seeder's millisecond
+// ISO strings) so they compare as text with stamps and `since`. Values that don't parse stay.
+fn normalize_updated_at(conn: &Connection, table_name: &str) -> Result<()> {
+    let rows: Vec<(String, String)> = {
+        let mut stmt = conn.prepare(&format!("SELECT id, updated_at FROM {} WHERE updated_at NOT GLOB ?1", table_name))?;
+        let rows = stmt.query_map(params![STAMP_GLOB], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<Result<_>>()?
+    };
+    let mut update = conn.prepare(&format!("UPDATE {} SET updated_at = ?1 WHERE id = ?2", table_name))?;
+    for (id, updated_at) in rows {
+        if let Some(stamp) = hlc::parse_stored(&updated_at) {
+            update.execute(params![hlc::format(stamp), id])?;
+        }
+    }
+    Ok(())
+}
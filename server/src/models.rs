@@ -23,7 +23,7 @@ pub struct JobRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -65,7 +65,7 @@ pub struct UserRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -122,7 +122,7 @@ pub struct CustomerRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -167,7 +167,7 @@ pub struct CalendarEventRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -210,7 +210,7 @@ pub struct PricebookRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -254,7 +254,7 @@ pub struct ProductRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -295,7 +295,7 @@ pub struct LocationRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -337,7 +337,7 @@ pub struct ProductItemRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -380,7 +380,7 @@ pub struct PricebookEntryRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -424,7 +424,7 @@ pub struct JobLineItemRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -472,7 +472,7 @@ pub struct QuoteRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -517,7 +517,7 @@ pub struct ObjectFeedRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -571,7 +571,7 @@ pub struct InvoiceRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -617,7 +617,7 @@ pub struct InvoiceLineItemRecord {
     pub id: String,
     pub tenant_id: String,
     pub status: String,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -670,7 +670,7 @@ pub struct ObjectMetadataRecord {
     pub tenant_id: Option<String>,
     pub object_name: String,
     pub data: ObjectMetadataData,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -714,7 +714,7 @@ pub struct LayoutDefinitionRecord {
     pub object_type: String,
     pub status: String,
     pub data: LayoutDefinitionData,
-    pub version: f64,
+    pub version: i64,
     pub created_by: Option<String>,
     pub modified_by: Option<String>,
     pub created_at: String,
@@ -751,6 +751,11 @@ pub struct SyncResponse {
 pub struct Meta {
     pub server_time: String,
     pub since: String,
+    pub schema_version: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub has_more: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -0,0 +1,235 @@
+// This file contains security profiles: the channels clients replicate through.
+//
+// A profile is a named, per-tenant definition of what its users may receive, written like
+// the `tables` / `filters` parameters of GET /sync (see `SyncScope`). Filter values of
+// `"$user_id"` stand for the user pulling, so one "Tech" profile can limit every tech to
+// their own jobs. Each user is assigned at most one profile.
+//
+// Tenants without profiles replicate everything to everyone, as before. Once a tenant
+// defines a profile, every pull and live stream must name a user (X-User-ID) that has one;
+// the profile's scope then narrows GET /sync, GET /sync/bootstrap, GET /sync/v2 and /ws.
+// Clients on the same profile share one broadcast channel, unless its filters mention
+// `$user_id`, which gives each user their own.
+
+use chrono::{SecondsFormat, Utc};
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::change_log::ChangeLogRecord;
+use crate::fetching::{ScopeError, SyncScope};
+
+// Stands for the pulling user's id in filter values.
+const USER_ID_PLACEHOLDER: &str = "$user_id";
+
+#[derive(Serialize, Debug)]
+pub struct SecurityProfile {
+    pub id: String,
+    pub tenant_id: String,
+    pub name: String,
+    /// `{"tables": [..], "filters": {..}}`, both optional.
+    pub definition: Value,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// What one caller may replicate.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    /// Identifies the broadcast channel: callers with the same key receive the same
+    /// changes. `None` for callers that see the whole tenant.
+    pub key: Option<String>,
+    pub scope: SyncScope,
+}
+
+impl Channel {
+    /// The whole tenant.
+    pub fn unrestricted() -> Self {
+        Channel { key: None, scope: SyncScope::default() }
+    }
+
+    /// The changes out of `changes` whose records the channel may see, in order.
+    pub fn visible(&self, conn: &Connection, tenant_id: &str, changes: Vec<ChangeLogRecord>) -> rusqlite::Result<Vec<ChangeLogRecord>> {
+        if self.scope.is_unrestricted() {
+            return Ok(changes);
+        }
+        let mut visible = Vec::new();
+        for change in changes {
+            if self.scope.allows(conn, tenant_id, &change.object_name, &change.record_id)? {
+                visible.push(change);
+            }
+        }
+        Ok(visible)
+    }
+}
+
+/// Reasons a profile can't be defined or a caller's channel can't be resolved.
+#[derive(Debug)]
+pub enum ProfileError {
+    /// The tenant uses profiles, so pulls must say who is pulling.
+    UserRequired,
+    /// The user isn't assigned a profile in a tenant that uses them.
+    NoProfile,
+    UnknownUser,
+    UnknownProfile,
+    InvalidDefinition(ScopeError),
+    Sql(rusqlite::Error),
+}
+
+impl ProfileError {
+    /// Stable error code returned to clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProfileError::UserRequired => "user_required",
+            ProfileError::NoProfile => "no_security_profile",
+            ProfileError::UnknownUser => "invalid_user",
+            ProfileError::UnknownProfile => "unknown_security_profile",
+            ProfileError::InvalidDefinition(e) => e.code(),
+            ProfileError::Sql(_) => "profile_lookup_failed",
+        }
+    }
+}
+
+impl std::fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileError::UserRequired => write!(f, "This tenant replicates through security profiles; send X-User-ID."),
+            ProfileError::NoProfile => write!(f, "The user has no security profile in this tenant."),
+            ProfileError::UnknownUser => write!(f, "The user does not exist in this tenant."),
+            ProfileError::UnknownProfile => write!(f, "No security profile with this name exists for the tenant."),
+            ProfileError::InvalidDefinition(e) => write!(f, "{}", e),
+            ProfileError::Sql(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ProfileError {
+    fn from(e: rusqlite::Error) -> Self {
+        ProfileError::Sql(e)
+    }
+}
+
+/// Create or replace the tenant's profile `name`. The definition is validated first;
+/// users assigned to the profile pick it up on their next pull or connection.
+pub fn define_profile(conn: &Connection, tenant_id: &str, name: &str, definition: &Value) -> Result<SecurityProfile, ProfileError> {
+    SyncScope::from_definition(&for_user(definition, "")).map_err(ProfileError::InvalidDefinition)?;
+    let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let updated = conn.execute(
+        "UPDATE security_profiles SET definition = ?3, updated_at = ?4 WHERE tenant_id = ?1 AND name = ?2",
+        params![tenant_id, name, definition.to_string(), now],
+    )?;
+    if updated == 0 {
+        conn.execute(
+            "INSERT INTO security_profiles (id, tenant_id, name, definition, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+            params![Uuid::new_v4().to_string(), tenant_id, name, definition.to_string(), now],
+        )?;
+    }
+    find_profile(conn, tenant_id, name)?.ok_or(ProfileError::UnknownProfile)
+}
+
+/// Every profile of the tenant, by name.
+pub fn list_profiles(conn: &Connection, tenant_id: &str) -> rusqlite::Result<Vec<SecurityProfile>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM security_profiles WHERE tenant_id = ?1 ORDER BY name", PROFILE_COLUMNS))?;
+    let profiles = stmt.query_map(params![tenant_id], profile_from_row)?;
+    profiles.collect()
+}
+
+/// Assign `user_id` to the tenant's profile `name`, or remove their assignment with `None`.
+pub fn assign_profile(conn: &Connection, tenant_id: &str, user_id: &str, name: Option<&str>) -> Result<(), ProfileError> {
+    let user_exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM users WHERE id = ?1 AND tenant_id = ?2)",
+        params![user_id, tenant_id],
+        |row| row.get(0),
+    )?;
+    if !user_exists {
+        return Err(ProfileError::UnknownUser);
+    }
+    let Some(name) = name else {
+        conn.execute("DELETE FROM security_profile_assignments WHERE user_id = ?1", params![user_id])?;
+        return Ok(());
+    };
+    let profile = find_profile(conn, tenant_id, name)?.ok_or(ProfileError::UnknownProfile)?;
+    conn.execute(
+        "INSERT INTO security_profile_assignments (user_id, tenant_id, profile_id, assigned_at) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(user_id) DO UPDATE SET tenant_id = excluded.tenant_id, profile_id = excluded.profile_id, assigned_at = excluded.assigned_at",
+        params![user_id, tenant_id, profile.id, Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)],
+    )?;
+    Ok(())
+}
+
+/// The channel `user_id` replicates `tenant_id` through.
+pub fn channel(conn: &Connection, tenant_id: &str, user_id: Option<&str>) -> Result<Channel, ProfileError> {
+    let uses_profiles: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM security_profiles WHERE tenant_id = ?1)",
+        params![tenant_id],
+        |row| row.get(0),
+    )?;
+    if !uses_profiles {
+        return Ok(Channel::unrestricted());
+    }
+    let user_id = user_id.ok_or(ProfileError::UserRequired)?;
+    let profile = conn
+        .query_row(
+            &format!(
+                "SELECT {} FROM security_profiles WHERE id = (SELECT profile_id FROM security_profile_assignments WHERE user_id = ?1 AND tenant_id = ?2)",
+                PROFILE_COLUMNS
+            ),
+            params![user_id, tenant_id],
+            profile_from_row,
+        )
+        .optional()?
+        .ok_or(ProfileError::NoProfile)?;
+
+    let scope = SyncScope::from_definition(&for_user(&profile.definition, user_id)).map_err(ProfileError::InvalidDefinition)?;
+    if scope.is_unrestricted() {
+        return Ok(Channel::unrestricted());
+    }
+    // A definition that mentions the user gives every user their own channel.
+    let personal = mentions_user(&profile.definition);
+    let key = if personal { format!("{}/{}", profile.id, user_id) } else { profile.id };
+    Ok(Channel { key: Some(key), scope })
+}
+
+const PROFILE_COLUMNS: &str = "id, tenant_id, name, definition, created_at, updated_at";
+
+fn profile_from_row(row: &rusqlite::Row) -> rusqlite::Result<SecurityProfile> {
+    let definition: String = row.get(3)?;
+    Ok(SecurityProfile {
+        id: row.get(0)?,
+        tenant_id: row.get(1)?,
+        name: row.get(2)?,
+        definition: serde_json::from_str(&definition).unwrap_or(Value::Null),
+        created_at: row.get(4)?,
+        updated_at: row.get(5)?,
+    })
+}
+
+fn find_profile(conn: &Connection, tenant_id: &str, name: &str) -> rusqlite::Result<Option<SecurityProfile>> {
+    conn.query_row(
+        &format!("SELECT {} FROM security_profiles WHERE tenant_id = ?1 AND name = ?2", PROFILE_COLUMNS),
+        params![tenant_id, name],
+        profile_from_row,
+    )
+    .optional()
+}
+
+// Whether any value `for_user` would replace appears in the definition.
+fn mentions_user(definition: &Value) -> bool {
+    match definition {
+        Value::String(value) => value == USER_ID_PLACEHOLDER,
+        Value::Array(values) => values.iter().any(mentions_user),
+        Value::Object(fields) => fields.values().any(mentions_user),
+        _ => false,
+    }
+}
+
+// The definition with every `"$user_id"` value replaced by `user_id`.
+fn for_user(definition: &Value, user_id: &str) -> Value {
+    match definition {
+        Value::String(value) if value == USER_ID_PLACEHOLDER => Value::String(user_id.to_string()),
+        Value::Array(values) => Value::Array(values.iter().map(|value| for_user(value, user_id)).collect()),
+        Value::Object(fields) => Value::Object(fields.iter().map(|(key, value)| (key.clone(), for_user(value, user_id))).collect()),
+        other => other.clone(),
+    }
+}
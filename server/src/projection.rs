@@ -0,0 +1,246 @@
+// This file contains the domain tables as a projection of the change_log.
+//
+// Every domain row is the result of folding its record's change_log rows through
+// `apply::apply_change`. Replaying the chain from genesis (or the latest compaction
+// snapshot) therefore reproduces the tables, which lets us rebuild them after a bad
+// deploy or a manual edit, and check that the live rows still match the chain.
+
+use std::collections::BTreeMap;
+
+use rusqlite::{Connection, Result, params};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::apply::{self, DELETED_STATUS};
+use crate::change_log::{self, ChangeLogRecord, ChangeOperation};
+use crate::compaction;
+use crate::merge::ReplayedRecord;
+use crate::routes::data_result::SYNCED_TABLES;
+
+// Rows are replayed in pages so very long chains don't have to fit in memory.
+const REPLAY_PAGE_SIZE: usize = 1000;
+
+/// A domain row as replaying its changes produces it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectedRecord {
+    pub state: ReplayedRecord,
+    pub version: i64,
+    pub created_by: String,
+    pub created_at: String,
+    pub modified_by: String,
+    pub updated_at: String,
+}
+
+impl ProjectedRecord {
+    /// Apply one change to a record that may not exist yet, matching `apply::apply_change`:
+    /// the first change inserts the row at version 0 with the client's `created_at`, every
+    /// later change bumps the version, and `updated_at` is the last change's HLC stamp.
+    /// A delete of a record that doesn't exist leaves it missing.
+    pub fn apply(record: &mut Option<ProjectedRecord>, change: &ChangeLogRecord) {
+        match record {
+            Some(record) => {
+                record.state.apply(change);
+                record.version += 1;
+                record.updated_at = change.stamped_at().to_string();
+            }
+            None if change.operation == ChangeOperation::Delete => {}
+            None => {
+                let mut state = ReplayedRecord::Missing;
+                state.apply(change);
+                *record = Some(ProjectedRecord {
+                    state,
+                    version: 0,
+                    created_by: change.user_id.clone(),
+                    created_at: change.created_at.clone(),
+                    modified_by: change.user_id.clone(),
+                    updated_at: change.stamped_at().to_string(),
+                });
+            }
+        }
+    }
+}
+
+/// Every record of a tenant, keyed by `(object_name, record_id)`.
+pub type Projection = BTreeMap<(String, String), ProjectedRecord>;
+
+/// Replay the tenant's chain in `sequence_id` order, starting from its latest snapshot.
+/// Returns the records and the `sequence_id` of the last replayed row.
+pub fn project_tenant(conn: &Connection, tenant_id: &str) -> Result<(Projection, i64)> {
+    let (mut sequence_id, _) = change_log::chain_start(conn, tenant_id)?;
+    let mut projection = match compaction::latest_snapshot(conn, tenant_id)? {
+        Some(snapshot) => compaction::snapshot_records(conn, &snapshot.id)?,
+        None => Projection::new(),
+    };
+    loop {
+        let page = change_log::fetch_changes_after(conn, tenant_id, sequence_id, Some(REPLAY_PAGE_SIZE))?;
+        let page_len = page.len();
+        for change in &page {
+            let key = (change.object_name.clone(), change.record_id.clone());
+            let mut record = projection.remove(&key);
+            ProjectedRecord::apply(&mut record, change);
+            if let Some(record) = record {
+                projection.insert(key, record);
+            }
+            sequence_id = change.sequence_id;
+        }
+        if page_len < REPLAY_PAGE_SIZE {
+            return Ok((projection, sequence_id));
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RebuildReport {
+    pub tenant_id: String,
+    /// The last change_log row the tables now reflect.
+    pub replayed_to_sequence_id: i64,
+    /// Rows of replayed records deleted from the domain tables before writing them back.
+    pub rows_removed: usize,
+    /// Rows the chain never wrote (e.g. seeded users); left as they are.
+    pub rows_untouched: usize,
+    /// Rows written back, tombstones included.
+    pub records_written: usize,
+    /// Records whose `object_name` has no synced table; they are left out.
+    pub records_skipped: usize,
+}
+
+/// Replace the tenant's domain rows of every record on the chain with the replayed
+/// records. Rows the chain doesn't cover (e.g. seeded users, `unexpected_row` in
+/// `check_projection`) are left alone. Run inside a transaction.
+pub fn rebuild_projection(conn: &Connection, tenant_id: &str) -> Result<RebuildReport> {
+    let (projection, replayed_to_sequence_id) = project_tenant(conn, tenant_id)?;
+    let mut report = RebuildReport {
+        tenant_id: tenant_id.to_string(),
+        replayed_to_sequence_id,
+        rows_removed: 0,
+        rows_untouched: 0,
+        records_written: 0,
+        records_skipped: 0,
+    };
+    let mut replayed = Vec::new();
+    for ((object_name, record_id), record) in &projection {
+        let Some(table) = apply::synced_table(object_name) else {
+            report.records_skipped += 1;
+            continue;
+        };
+        report.rows_removed += apply::delete_record_row(conn, table, tenant_id, record_id)?;
+        replayed.push((table, record_id, record));
+    }
+    for table in SYNCED_TABLES {
+        report.rows_untouched += apply::count_tenant_rows(conn, table, tenant_id)?;
+    }
+    for (table, record_id, record) in replayed {
+        apply::restore_record(conn, table, tenant_id, record_id, record)?;
+        report.records_written += 1;
+    }
+    Ok(report)
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProjectionReport {
+    pub tenant_id: String,
+    pub consistent: bool,
+    /// The last change_log row the replay covered.
+    pub replayed_to_sequence_id: i64,
+    pub records_checked: usize,
+    pub mismatches: Vec<ProjectionMismatch>,
+}
+
+/// One record whose live row differs from the replayed chain.
+#[derive(Serialize, Debug)]
+pub struct ProjectionMismatch {
+    pub object_name: String,
+    pub record_id: String,
+    /// `missing_row` (the chain has the record, the table doesn't), `unexpected_row`
+    /// (the table has a row the chain never wrote), `status_mismatch`, `version_mismatch`
+    /// or `data_mismatch`.
+    pub kind: &'static str,
+    /// The replayed value; `null` for `unexpected_row`.
+    pub expected: Value,
+    /// The live value; `null` for `missing_row`.
+    pub found: Value,
+}
+
+// A live domain row, as far as the check compares it.
+struct LiveRow {
+    data: Value,
+    deleted: bool,
+    version: i64,
+}
+
+/// Replay the tenant's chain and compare it with the live domain tables record by record.
+pub fn check_projection(conn: &Connection, tenant_id: &str) -> Result<ProjectionReport> {
+    let (projection, replayed_to_sequence_id) = project_tenant(conn, tenant_id)?;
+    let mut live = live_rows(conn, tenant_id)?;
+    let mut mismatches = Vec::new();
+
+    for ((object_name, record_id), record) in &projection {
+        let mismatch = |kind, expected, found| ProjectionMismatch {
+            object_name: object_name.clone(),
+            record_id: record_id.clone(),
+            kind,
+            expected,
+            found,
+        };
+        let (document, deleted) = match &record.state {
+            ReplayedRecord::Live(document) => (document, false),
+            ReplayedRecord::Deleted(document) => (document, true),
+            ReplayedRecord::Missing => continue,
+        };
+        let Some(row) = live.remove(&(object_name.clone(), record_id.clone())) else {
+            mismatches.push(mismatch("missing_row", document.clone(), Value::Null));
+            continue;
+        };
+        if row.deleted != deleted {
+            mismatches.push(mismatch("status_mismatch", json!(status_name(deleted)), json!(status_name(row.deleted))));
+        }
+        if row.version != record.version {
+            mismatches.push(mismatch("version_mismatch", json!(record.version), json!(row.version)));
+        }
+        if row.data != *document {
+            mismatches.push(mismatch("data_mismatch", document.clone(), row.data));
+        }
+    }
+    let records_checked = projection.len() + live.len();
+    for ((object_name, record_id), row) in live {
+        mismatches.push(ProjectionMismatch { object_name, record_id, kind: "unexpected_row", expected: Value::Null, found: row.data });
+    }
+
+    Ok(ProjectionReport {
+        tenant_id: tenant_id.to_string(),
+        consistent: mismatches.is_empty(),
+        replayed_to_sequence_id,
+        records_checked,
+        mismatches,
+    })
+}
+
+fn status_name(deleted: bool) -> &'static str {
+    if deleted { DELETED_STATUS } else { "active" }
+}
+
+// Every row of the tenant across the synced tables, keyed like `Projection`.
+fn live_rows(conn: &Connection, tenant_id: &str) -> Result<BTreeMap<(String, String), LiveRow>> {
+    let mut rows = BTreeMap::new();
+    for table in SYNCED_TABLES {
+        let status_column = if table.has_status { "status" } else { "NULL" };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, data, {}, version FROM {} WHERE tenant_id = ?1",
+            status_column, table.table_name
+        ))?;
+        let table_rows = stmt.query_map(params![tenant_id], |row| {
+            let data: String = row.get(1)?;
+            let status: Option<String> = row.get(2)?;
+            Ok((row.get::<_, String>(0)?, LiveRow {
+                data: serde_json::from_str(&data).unwrap_or(Value::String(data)),
+                deleted: status.as_deref() == Some(DELETED_STATUS),
+                version: row.get(3)?,
+            }))
+        })?;
+        for row in table_rows {
+            let (record_id, row) = row?;
+            rows.insert((table.object_name.to_string(), record_id), row);
+        }
+    }
+    Ok(rows)
+}
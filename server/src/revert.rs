@@ -0,0 +1,159 @@
+// This file contains undo: reverting a change by appending its inverse.
+//
+// The chain is append-only, so a revert never touches the reverted row. It computes the
+// merge patch that takes the record from just after the change back to just before it
+// (limited to the fields the change touched) and appends that as a new change.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::apply::ApplyError;
+use crate::change_log::{self, ChangeLogRecord, ChangeOperation};
+use crate::history;
+use crate::merge;
+use crate::routes::sync::{ServerChange, ServerChanges};
+
+/// A later change that touched a field the revert would reset.
+#[derive(Serialize, Debug, Clone)]
+pub struct RevertConflict {
+    pub change_id: String,
+    pub sequence_id: i64,
+    pub user_id: String,
+    pub created_at: String,
+    /// The overlapping fields; `["*"]` when either side affects the whole record.
+    pub fields: Vec<String>,
+}
+
+/// An appended revert.
+#[derive(Serialize, Debug)]
+pub struct Revert {
+    pub reverted_change_id: String,
+    /// The compensating change, as stored in the change_log.
+    pub change: ChangeLogRecord,
+    /// Later changes whose edits the revert overwrote (only with `force`).
+    pub conflicts: Vec<RevertConflict>,
+}
+
+/// Reasons a change cannot be reverted.
+#[derive(Debug)]
+pub enum RevertError {
+    /// No change with that id in the tenant's change_log.
+    NotFound,
+    /// The change's predecessor state was compacted away.
+    Compacted,
+    /// Deletes win over updates, so a tombstone can't be brought back.
+    DeleteNotRevertible,
+    /// The change didn't alter the record, so its inverse is empty.
+    NothingToRevert,
+    /// Later changes touched the same fields; revert with `force` to overwrite them.
+    Conflict(Vec<RevertConflict>),
+    Apply(ApplyError),
+}
+
+impl RevertError {
+    /// Stable error code returned to clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RevertError::NotFound => "change_not_found",
+            RevertError::Compacted => "position_compacted",
+            RevertError::DeleteNotRevertible => "delete_not_revertible",
+            RevertError::NothingToRevert => "nothing_to_revert",
+            RevertError::Conflict(_) => "revert_conflict",
+            RevertError::Apply(e) => e.code(),
+        }
+    }
+}
+
+impl std::fmt::Display for RevertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RevertError::NotFound => write!(f, "No change with this id exists for the tenant."),
+            RevertError::Compacted => write!(f, "The record's state before this change was compacted away."),
+            RevertError::DeleteNotRevertible => write!(f, "Deletes cannot be reverted."),
+            RevertError::NothingToRevert => write!(f, "The change did not modify the record."),
+            RevertError::Conflict(_) => write!(f, "Later changes modified the same fields. Retry with force=true to overwrite them."),
+            RevertError::Apply(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for RevertError {
+    fn from(e: rusqlite::Error) -> Self {
+        RevertError::Apply(ApplyError::Sql(e))
+    }
+}
+
+impl From<ApplyError> for RevertError {
+    fn from(e: ApplyError) -> Self {
+        RevertError::Apply(e)
+    }
+}
+
+/// Append the inverse of change `change_id`, authored by `user_id`, and apply it to the
+/// domain table. A change that created its record is reverted by deleting the record.
+/// Later changes to the same fields make this fail with `Conflict` unless `force` is set,
+/// in which case they are overwritten and reported. Commit `batch` and publish the rows it returns to broadcast the revert.
+pub fn revert_change(batch: &mut ServerChanges, change_id: &str, user_id: &str, force: bool) -> Result<Revert, RevertError> {
+    let conn = batch.conn();
+    let tenant_id = batch.tenant_id();
+    let change = change_log::find_change(conn, change_id)?
+        .filter(|change| change.tenant_id == tenant_id)
+        .ok_or(RevertError::NotFound)?;
+    let at = |sequence_id| history::record_at(conn, tenant_id, &change.object_name, &change.record_id, sequence_id);
+    let before = at(change.sequence_id - 1)?.ok_or(RevertError::Compacted)?;
+    let after = at(change.sequence_id)?.ok_or(RevertError::Compacted)?;
+
+    if change.operation == ChangeOperation::Delete {
+        return Err(if before.deleted == after.deleted { RevertError::NothingToRevert } else { RevertError::DeleteNotRevertible });
+    }
+
+    let (operation, inverse) = match (&before.document, &after.document) {
+        // The change created the record.
+        (None, _) => (ChangeOperation::Delete, Value::Object(Default::default())),
+        (Some(before), Some(after)) => {
+            let mut inverse = merge::diff_patch(after, before);
+            // Only undo what this change did; fields it didn't touch keep later edits.
+            if let (Some(fields), Some(inverse_fields)) = (merge::touched_fields(change.operation, &change.change_data), inverse.as_object_mut()) {
+                inverse_fields.retain(|field, _| fields.contains(field));
+            }
+            if inverse.as_object().is_some_and(|fields| fields.is_empty()) {
+                return Err(RevertError::NothingToRevert);
+            }
+            (ChangeOperation::Update, inverse)
+        }
+        (Some(_), None) => return Err(RevertError::NothingToRevert),
+    };
+
+    let later = change_log::fetch_record_changes(conn, tenant_id, &change.object_name, &change.record_id, None)?;
+    let conflicts: Vec<RevertConflict> = later
+        .iter()
+        .filter(|other| other.sequence_id > change.sequence_id)
+        .filter_map(|other| {
+            // Deleting the record undoes every later edit to it.
+            let fields = if operation == ChangeOperation::Delete {
+                Default::default()
+            } else {
+                merge::overlapping_fields(operation, &inverse, other)?
+            };
+            Some(RevertConflict {
+                change_id: other.id.clone(),
+                sequence_id: other.sequence_id,
+                user_id: other.user_id.clone(),
+                created_at: other.created_at.clone(),
+                fields: if fields.is_empty() { vec!["*".to_string()] } else { fields.into_iter().collect() },
+            })
+        })
+        .collect();
+    if !conflicts.is_empty() && !force {
+        return Err(RevertError::Conflict(conflicts));
+    }
+
+    let appended = batch.append(user_id, &ServerChange {
+        object_name: &change.object_name,
+        record_id: &change.record_id,
+        operation,
+        changes: &inverse,
+        expected_version: None,
+    })?;
+    Ok(Revert { reverted_change_id: change.id, change: appended, conflicts })
+}
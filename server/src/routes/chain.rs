@@ -0,0 +1,66 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use rusqlite::params;
+use serde_json::json;
+
+use crate::checkpoint;
+use crate::verify;
+
+use super::sync::AppState;
+
+// Chain audit for one tenant: GET /tenants/{tenant_id}/chain/verify
+//
+// Re-walks the tenant's change_log from genesis, recomputing each row's
+// `state_hash` and checking it links to the row before it. Returns 200 with a
+// `ChainReport` either way; `valid == false` comes with `first_broken_link` or a failed
+// `latest_checkpoint`.
+// The latest checkpoint's signature is checked against this server's key.
+pub async fn verify_chain_handler(
+    State(state): State<AppState>,
+    Path(tenant_id): Path<String>,
+) -> impl IntoResponse {
+    let tenant_count: rusqlite::Result<i64> = state.db.lock().unwrap().query_row(
+        "SELECT COUNT(1) FROM tenants WHERE id = ?1",
+        params![&tenant_id],
+        |row| row.get(0),
+    );
+    if let Ok(0) = tenant_count {
+        return (StatusCode::NOT_FOUND, Json(json!({
+            "status": "error",
+            "error": "invalid_tenant",
+            "message": "Provided tenant_id does not exist.",
+            "details": { "tenant_id": tenant_id }
+        }))).into_response();
+    }
+
+    // Locks the database per page rather than for the whole walk.
+    match verify::verify_chain(&state.db, &tenant_id, &state.signer.public_key()) {
+        Ok(report) => (StatusCode::OK, Json(report)).into_response(),
+        Err(e) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response()
+        }
+    }
+}
+
+// Latest signed checkpoint for one tenant: GET /tenants/{tenant_id}/checkpoints/latest
+//
+// Returns 404 `no_checkpoint` until the tenant has changes and the periodic
+// signer has run. Clients verify `signature` against the pinned `public_key`.
+pub async fn latest_checkpoint_handler(
+    State(state): State<AppState>,
+    Path(tenant_id): Path<String>,
+) -> impl IntoResponse {
+    let conn = state.db.lock().unwrap();
+
+    match checkpoint::latest_checkpoint(&conn, &tenant_id, None) {
+        Ok(Some(checkpoint)) => (StatusCode::OK, Json(checkpoint)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(json!({
+            "status": "error",
+            "error": "no_checkpoint",
+            "message": "No checkpoint has been issued for this tenant yet.",
+            "details": { "tenant_id": tenant_id }
+        }))).into_response(),
+        Err(e) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response()
+        }
+    }
+}
@@ -3,26 +3,45 @@
 
 use rusqlite::{Connection, Result};
 use crate::models::*;
-use crate::fetching::fetch_all;
+use crate::fetching::{fetch_all, DataPage, SyncedTable};
 
 
-pub fn get_data_result(conn: &Connection, tenant_id: &str, since: &str) -> Result<ResponseData> {
+pub fn get_data_result(conn: &Connection, tenant_id: &str, since: &str, page: &mut DataPage) -> Result<ResponseData> {
     Ok(ResponseData {
-        users: fetch_all(&conn, "users", tenant_id, since, crate::models::user_record_from_row)?,
-        customers: fetch_all(&conn, "customers", tenant_id, since, crate::models::customer_record_from_row)?,
-        jobs: fetch_all(&conn, "jobs", tenant_id, since, crate::models::job_record_from_row)?,
-        calendar_events: fetch_all(&conn, "calendar_events", tenant_id, since, crate::models::calendar_event_record_from_row)?,
-        pricebooks: fetch_all(&conn, "pricebooks", tenant_id, since, crate::models::pricebook_record_from_row)?,
-        products: fetch_all(&conn, "products", tenant_id, since, crate::models::product_record_from_row)?,
-        locations: fetch_all(&conn, "locations", tenant_id, since, crate::models::location_record_from_row)?,
-        product_items: fetch_all(&conn, "product_items", tenant_id, since, crate::models::product_item_record_from_row)?,
-        pricebook_entries: fetch_all(&conn, "pricebook_entries", tenant_id, since, crate::models::pricebook_entry_record_from_row)?,
-        job_line_items: fetch_all(&conn, "job_line_items", tenant_id, since, crate::models::job_line_item_record_from_row)?,
-        quotes: fetch_all(&conn, "quotes", tenant_id, since, crate::models::quote_record_from_row)?,
-        object_feeds: fetch_all(&conn, "object_feeds", tenant_id, since, crate::models::object_feed_record_from_row)?,
-        invoices: fetch_all(&conn, "invoices", tenant_id, since, crate::models::invoice_record_from_row)?,
-        invoice_line_items: fetch_all(&conn, "invoice_line_items", tenant_id, since, crate::models::invoice_line_item_record_from_row)?,
-        object_metadata: fetch_all(&conn, "object_metadata", tenant_id, since, crate::models::object_metadata_record_from_row)?,
-        layout_definitions: fetch_all(&conn, "layout_definitions", tenant_id, since, crate::models::layout_definition_record_from_row)?,
+        users: fetch_all(conn, "users", tenant_id, since, page, crate::models::user_record_from_row)?,
+        customers: fetch_all(conn, "customers", tenant_id, since, page, crate::models::customer_record_from_row)?,
+        jobs: fetch_all(conn, "jobs", tenant_id, since, page, crate::models::job_record_from_row)?,
+        calendar_events: fetch_all(conn, "calendar_events", tenant_id, since, page, crate::models::calendar_event_record_from_row)?,
+        pricebooks: fetch_all(conn, "pricebooks", tenant_id, since, page, crate::models::pricebook_record_from_row)?,
+        products: fetch_all(conn, "products", tenant_id, since, page, crate::models::product_record_from_row)?,
+        locations: fetch_all(conn, "locations", tenant_id, since, page, crate::models::location_record_from_row)?,
+        product_items: fetch_all(conn, "product_items", tenant_id, since, page, crate::models::product_item_record_from_row)?,
+        pricebook_entries: fetch_all(conn, "pricebook_entries", tenant_id, since, page, crate::models::pricebook_entry_record_from_row)?,
+        job_line_items: fetch_all(conn, "job_line_items", tenant_id, since, page, crate::models::job_line_item_record_from_row)?,
+        quotes: fetch_all(conn, "quotes", tenant_id, since, page, crate::models::quote_record_from_row)?,
+        object_feeds: fetch_all(conn, "object_feeds", tenant_id, since, page, crate::models::object_feed_record_from_row)?,
+        invoices: fetch_all(conn, "invoices", tenant_id, since, page, crate::models::invoice_record_from_row)?,
+        invoice_line_items: fetch_all(conn, "invoice_line_items", tenant_id, since, page, crate::models::invoice_line_item_record_from_row)?,
+        object_metadata: fetch_all(conn, "object_metadata", tenant_id, since, page, crate::models::object_metadata_record_from_row)?,
+        layout_definitions: fetch_all(conn, "layout_definitions", tenant_id, since, page, crate::models::layout_definition_record_from_row)?,
     })
-}
\ No newline at end of file
+}
+
+pub const SYNCED_TABLES: &[SyncedTable] = &[
+    SyncedTable { object_name: "user", table_name: "users", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<UserData>(data).map(|_| ()) },
+    SyncedTable { object_name: "customer", table_name: "customers", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<CustomerData>(data).map(|_| ()) },
+    SyncedTable { object_name: "job", table_name: "jobs", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<JobData>(data).map(|_| ()) },
+    SyncedTable { object_name: "calendar_event", table_name: "calendar_events", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<CalendarEventData>(data).map(|_| ()) },
+    SyncedTable { object_name: "pricebook", table_name: "pricebooks", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<PricebookData>(data).map(|_| ()) },
+    SyncedTable { object_name: "product", table_name: "products", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<ProductData>(data).map(|_| ()) },
+    SyncedTable { object_name: "location", table_name: "locations", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<LocationData>(data).map(|_| ()) },
+    SyncedTable { object_name: "product_item", table_name: "product_items", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<ProductItemData>(data).map(|_| ()) },
+    SyncedTable { object_name: "pricebook_entry", table_name: "pricebook_entries", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<PricebookEntryData>(data).map(|_| ()) },
+    SyncedTable { object_name: "job_line_item", table_name: "job_line_items", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<JobLineItemData>(data).map(|_| ()) },
+    SyncedTable { object_name: "quote", table_name: "quotes", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<QuoteData>(data).map(|_| ()) },
+    SyncedTable { object_name: "object_feed", table_name: "object_feeds", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<ObjectFeedData>(data).map(|_| ()) },
+    SyncedTable { object_name: "invoice", table_name: "invoices", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<InvoiceData>(data).map(|_| ()) },
+    SyncedTable { object_name: "invoice_line_item", table_name: "invoice_line_items", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<InvoiceLineItemData>(data).map(|_| ()) },
+    SyncedTable { object_name: "object_metadata", table_name: "object_metadata", has_status: false, has_object_type: false, validate_data: |data| serde_json::from_str::<ObjectMetadataData>(data).map(|_| ()) },
+    SyncedTable { object_name: "layout_definition", table_name: "layout_definitions", has_status: true, has_object_type: true, validate_data: |data| serde_json::from_str::<LayoutDefinitionData>(data).map(|_| ()) },
+];
\ No newline at end of file
@@ -1,2 +1,5 @@
 pub mod sync;
-pub mod data_result;
\ No newline at end of file
+pub mod data_result;
+pub mod ws;
+pub mod chain;
+pub mod records;
@@ -0,0 +1,271 @@
+use axum::{extract::{Path, Query, State}, http::HeaderMap, http::StatusCode, response::IntoResponse, Json};
+use rusqlite::params;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::apply::{self, ApplyError};
+use crate::change_log;
+use crate::history::{self, PointInTimeRecord};
+use crate::hlc;
+use crate::revert::{self, RevertError};
+
+use super::sync::{pull_channel, AppState, ServerChanges};
+
+#[derive(Deserialize)]
+pub struct RecordParams {
+    pub tenant_id: String,
+}
+
+// Change history of one record: GET /records/{object_name}/{id}/history?tenant_id=...
+//
+// Lists the record's change_log rows oldest first with who made each change and
+// when, plus the full document after each step. Returns 404 `record_not_found`
+// when the record has no changes, or is outside the caller's security profile.
+pub async fn record_history_handler(
+    State(state): State<AppState>,
+    Path((object_name, record_id)): Path<(String, String)>,
+    Query(params): Query<RecordParams>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if apply::synced_table(&object_name).is_none() {
+        return (StatusCode::BAD_REQUEST, Json(json!({
+            "status": "error",
+            "error": "unknown_object_name",
+            "message": "Provided object_name does not map to a synced table.",
+            "details": { "object_name": object_name }
+        }))).into_response();
+    }
+
+    let conn = state.db.lock().unwrap();
+    // The same records a pull would return; others look like they have no history.
+    let visible = match pull_channel(&conn, &headers, &params.tenant_id) {
+        Ok(channel) => channel.scope.allows(&conn, &params.tenant_id, &object_name, &record_id),
+        Err(error) => return error.into_response(),
+    };
+    let history = visible.and_then(|visible| {
+        if visible { history::record_history(&conn, &params.tenant_id, &object_name, &record_id) } else { Ok(None) }
+    });
+    match history {
+        Ok(Some(history)) => (StatusCode::OK, Json(history)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(json!({
+            "status": "error",
+            "error": "record_not_found",
+            "message": "The change_log has no changes for this record.",
+            "details": { "tenant_id": params.tenant_id, "object_name": object_name, "record_id": record_id }
+        }))).into_response(),
+        Err(e) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RecordAtParams {
+    pub tenant_id: String,
+    /// Reconstruct at the chain position with this `state_hash`...
+    pub state_hash: Option<String>,
+    /// ...or at the last change the server had accepted by this RFC 3339 timestamp.
+    pub at: Option<String>,
+}
+
+// Point-in-time record: GET /records/{object_name}/{id}/at?tenant_id=...&state_hash=... (or &at=...)
+//
+// Replays the record's merge patches up to the requested chain position and returns
+// the document with the `sequence_id` and `state_hash` it was reconstructed at.
+// Positions before the latest compaction anchor return 410 `position_compacted`.
+// Records outside the caller's security profile return 404 `record_not_found`.
+pub async fn record_at_handler(
+    State(state): State<AppState>,
+    Path((object_name, record_id)): Path<(String, String)>,
+    Query(params): Query<RecordAtParams>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if apply::synced_table(&object_name).is_none() {
+        return (StatusCode::BAD_REQUEST, Json(json!({
+            "status": "error",
+            "error": "unknown_object_name",
+            "message": "Provided object_name does not map to a synced table.",
+            "details": { "object_name": object_name }
+        }))).into_response();
+    }
+
+    let conn = state.db.lock().unwrap();
+    match pull_channel(&conn, &headers, &params.tenant_id).map(|channel| channel.scope.allows(&conn, &params.tenant_id, &object_name, &record_id)) {
+        Ok(Ok(true)) => {}
+        Ok(Ok(false)) => {
+            return (StatusCode::NOT_FOUND, Json(json!({
+                "status": "error",
+                "error": "record_not_found",
+                "message": "The change_log has no changes for this record.",
+                "details": { "tenant_id": params.tenant_id, "object_name": object_name, "record_id": record_id }
+            }))).into_response();
+        }
+        Ok(Err(e)) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+        Err(error) => return error.into_response(),
+    }
+
+    let position = match (&params.state_hash, &params.at) {
+        (Some(state_hash), None) => match change_log::find_sequence_id(&conn, &params.tenant_id, state_hash) {
+            Ok(Some(sequence_id)) => Some((sequence_id, state_hash.clone())),
+            Ok(None) => {
+                return (StatusCode::NOT_FOUND, Json(json!({
+                    "status": "error",
+                    "error": "unknown_state_hash",
+                    "message": "The state_hash is not on the tenant's chain, or precedes its compaction anchor.",
+                    "details": { "tenant_id": params.tenant_id, "state_hash": state_hash }
+                }))).into_response();
+            }
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+        },
+        (None, Some(at)) => {
+            let Some(at) = hlc::normalize(at) else {
+                return (StatusCode::BAD_REQUEST, Json(json!({
+                    "status": "error",
+                    "error": "invalid_timestamp",
+                    "message": "at must be an RFC 3339 timestamp.",
+                    "details": { "at": at }
+                }))).into_response();
+            };
+            match change_log::position_at(&conn, &params.tenant_id, &at) {
+                Ok(position) => position,
+                Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+            }
+        }
+        _ => {
+            return (StatusCode::BAD_REQUEST, Json(json!({
+                "status": "error",
+                "error": "invalid_point_in_time",
+                "message": "Provide exactly one of state_hash or at.",
+                "details": {}
+            }))).into_response();
+        }
+    };
+
+    let record = match position {
+        Some((sequence_id, state_hash)) => history::record_at(&conn, &params.tenant_id, &object_name, &record_id, sequence_id)
+            .map(|state| state.map(|state| PointInTimeRecord {
+                tenant_id: params.tenant_id.clone(),
+                object_name: object_name.clone(),
+                record_id: record_id.clone(),
+                sequence_id,
+                state_hash,
+                state,
+            })),
+        None => Ok(None),
+    };
+    match record {
+        Ok(Some(record)) => (StatusCode::OK, Json(record)).into_response(),
+        Ok(None) => (StatusCode::GONE, Json(json!({
+            "status": "error",
+            "error": "position_compacted",
+            "message": "That point precedes the tenant's compaction anchor; its changes are no longer stored.",
+            "details": { "tenant_id": params.tenant_id, "state_hash": params.state_hash, "at": params.at }
+        }))).into_response(),
+        Err(e) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RevertParams {
+    pub tenant_id: String,
+    /// Revert even when later changes touched the same fields; they are listed in `conflicts`.
+    pub force: Option<bool>,
+}
+
+// Undo one change: POST /changes/{change_id}/revert?tenant_id=...[&force=true]
+//
+// Appends the inverse merge patch of the change (against the record just before it)
+// as a new change authored by X-User-ID, applies it and broadcasts it. When later
+// changes touched the same fields this returns 409 `revert_conflict` with the list,
+// unless `force=true`. Changes to records outside the caller's security profile
+// return 404 `change_not_found`.
+pub async fn revert_change_handler(
+    State(state): State<AppState>,
+    Path(change_id): Path<String>,
+    Query(params): Query<RevertParams>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let mut conn = state.db.lock().unwrap();
+
+    // FAKE AUTH: same as POST /sync.
+    let user_id = headers
+        .get("X-User-ID")
+        .and_then(|header| header.to_str().ok())
+        .unwrap_or("fake_user_from_header");
+    let user_count: rusqlite::Result<i64> = conn.query_row(
+        "SELECT COUNT(1) FROM users WHERE id = ?1 AND tenant_id = ?2",
+        params![user_id, params.tenant_id],
+        |row| row.get(0),
+    );
+    if let Ok(0) = user_count {
+        return (StatusCode::BAD_REQUEST, Json(json!({
+            "status": "error",
+            "error": "invalid_user",
+            "message": "X-User-ID does not refer to an existing user.",
+            "details": { "user_id": user_id }
+        }))).into_response();
+    }
+
+    // Only records the caller may pull can be reverted; others look like the change doesn't exist.
+    let visible = pull_channel(&conn, &headers, &params.tenant_id).map(|channel| {
+        change_log::find_change(&conn, &change_id).and_then(|change| match change.filter(|change| change.tenant_id == params.tenant_id) {
+            Some(change) => channel.scope.allows(&conn, &params.tenant_id, &change.object_name, &change.record_id),
+            None => Ok(true),
+        })
+    });
+    match visible {
+        Ok(Ok(true)) => {}
+        Ok(Ok(false)) => {
+            let e = RevertError::NotFound;
+            return (StatusCode::NOT_FOUND, Json(json!({
+                "status": "error",
+                "error": e.code(),
+                "message": e.to_string(),
+                "details": { "tenant_id": params.tenant_id, "change_id": change_id }
+            }))).into_response();
+        }
+        Ok(Err(e)) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+        Err(error) => return error.into_response(),
+    }
+
+    let mut batch = match ServerChanges::begin(&mut conn, &params.tenant_id) {
+        Ok(batch) => batch,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+    };
+    let revert = match revert::revert_change(&mut batch, &change_id, user_id, params.force.unwrap_or(false)) {
+        Ok(revert) => revert,
+        Err(e) => {
+            let status = match &e {
+                RevertError::NotFound => StatusCode::NOT_FOUND,
+                RevertError::Compacted => StatusCode::GONE,
+                RevertError::DeleteNotRevertible | RevertError::NothingToRevert | RevertError::Apply(ApplyError::InvalidDocument(_)) => {
+                    StatusCode::UNPROCESSABLE_ENTITY
+                }
+                RevertError::Conflict(_) | RevertError::Apply(ApplyError::RecordExists | ApplyError::VersionConflict { .. }) => StatusCode::CONFLICT,
+                RevertError::Apply(ApplyError::UnknownObjectName | ApplyError::DeleteUnsupported) => StatusCode::BAD_REQUEST,
+                RevertError::Apply(ApplyError::ForeignRecord) => StatusCode::FORBIDDEN,
+                RevertError::Apply(ApplyError::Sql(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            let mut details = json!({ "tenant_id": params.tenant_id, "change_id": change_id });
+            if let RevertError::Conflict(conflicts) = &e {
+                details["conflicts"] = json!(conflicts);
+            }
+            return (status, Json(json!({
+                "status": "error",
+                "error": e.code(),
+                "message": e.to_string(),
+                "details": details
+            }))).into_response();
+        }
+    };
+    let appended = match batch.commit() {
+        Ok(appended) => appended,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+    };
+
+    // Only committed rows are visible to subscribers.
+    state.broadcaster.publish(&conn, &params.tenant_id, appended);
+    (StatusCode::OK, Json(revert)).into_response()
+}
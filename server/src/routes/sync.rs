@@ -1,61 +1,52 @@
 use axum::{http::HeaderMap, http::StatusCode, response::IntoResponse, Json, extract::{Query, State}};
-use chrono::{SecondsFormat, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
 use rusqlite::{Connection, Result, params};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::{Arc, Mutex};
-// NOTE: This requires the `sha2` crate. Add `sha2 = "0.10"` to Cargo.toml.
-use sha2::{Sha256, Digest};
 
+use crate::apply::{self, ApplyError, RecordChange};
+use crate::broadcast::ChangeBroadcaster;
+use crate::checkpoint::{self, Checkpoint, CheckpointSigner};
+use crate::compaction;
+use crate::change_log::{self, ChangeLogRecord, ChangeOperation, NewChange};
+use crate::fetching::{self, DataPage, SyncScope, TableCursor};
+use crate::hashing::{self, ChangeHashInput, HashVersion, ACCEPTED_HASH_VERSIONS};
+use crate::hlc;
+use crate::merge;
 use crate::models::*;
+use crate::profiles::{self, Channel, ProfileError};
+use crate::schema_version;
 
 use super::data_result;
 
-// When a tenant has no previous changes, we use a known "genesis" hash
-// as the starting point for the hash chain. This ensures the chain is always
-// valid and verifiable from the very first entry.
-const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000"; // 64 zeros
-
 // Shared state (same as in main.rs)
 #[derive(Clone)]
 pub struct AppState {
     pub db: Arc<Mutex<Connection>>,
+    pub broadcaster: ChangeBroadcaster,
+    pub signer: CheckpointSigner,
 }
 
-/// Apply a change to the jobs table with upsert semantics for client-generated IDs.
-/// - UPDATE merges the JSON patch; if no row exists, INSERT a new record.
-fn apply_job_change(
-    tx: &rusqlite::Transaction,
-    overlay: &OverlayRecord,
-    user_id: &str,
-    changes_json: &str,
-) -> rusqlite::Result<()> {
-    let updated = tx.execute(
-        "UPDATE jobs SET data = json_patch(data, ?1), version = version + 1, updated_at = ?2 WHERE id = ?3",
-        params![changes_json, overlay.created_at, overlay.object_id],
-    )?;
-
-    if updated == 0 {
-        // No existing row; treat as CREATE and insert a new record.
-        tx.execute(
-            "INSERT INTO jobs (id, tenant_id, status, version, created_by, modified_by, created_at, updated_at, object_name, object_type, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
-            params![
-                overlay.object_id,   // id
-                overlay.tenant_id,   // tenant_id
-                "active",            // status (default for new records)
-                0,                    // version
-                user_id,              // created_by
-                user_id,              // modified_by
-                overlay.created_at,  // created_at
-                overlay.created_at,  // updated_at
-                overlay.object_name, // object_name
-                overlay.object_name, // object_type (using object_name for now)
-                changes_json,        // data (initial document)
-            ],
-        )?;
-    }
-
-    Ok(())
+/// The channel the caller (X-User-ID) replicates `tenant_id` through, or the error response
+/// to return when they may not pull it.
+pub(crate) fn pull_channel(conn: &Connection, headers: &HeaderMap, tenant_id: &str) -> std::result::Result<Channel, (StatusCode, Json<Value>)> {
+    let user_id = headers.get("X-User-ID").and_then(|header| header.to_str().ok());
+    profiles::channel(conn, tenant_id, user_id).map_err(|e| {
+        let status = match e {
+            ProfileError::UserRequired => StatusCode::UNAUTHORIZED,
+            ProfileError::NoProfile => StatusCode::FORBIDDEN,
+            ProfileError::Sql(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(json!({
+            "status": "error",
+            "error": e.code(),
+            "message": e.to_string(),
+            "details": { "tenant_id": tenant_id, "user_id": user_id }
+        })))
+    })
 }
 
 // Query params for GET /sync
@@ -63,26 +54,117 @@ fn apply_job_change(
 pub struct SyncParams {
     pub tenant_id: String,
     pub since: Option<String>,
+    /// Maximum number of rows across all tables; omit to get everything at once.
+    pub limit: Option<usize>,
+    /// `meta.next_cursor` from the previous page.
+    pub cursor: Option<String>,
+    /// Comma-separated tables to include (e.g. `jobs,calendar_events`); omit for all of them.
+    pub tables: Option<String>,
+    /// Per-table conditions on the `data` document, as JSON; see `SyncScope::parse`.
+    pub filters: Option<String>,
 }
 
+// Largest page a client may ask for, for both GET /sync and GET /sync/v2.
+const MAX_PAGE_SIZE: usize = 5000;
+
 // Main handler for GET /sync
+//
+// Returns every row with `updated_at > since`. `updated_at` is the server's HLC stamp of
+// the last change to the row, never a client clock, so a device whose clock is behind
+// can't write rows that sort before other clients' `since`. Deleted records are included
+// as tombstones (`status == "deleted"`) so clients can drop their local copies.
+//
+// `meta.server_time` is the watermark of the data read: the latest `updated_at` in the
+// response's snapshot (see `fetching::watermark`). Clients send it back as their next
+// `since`, which then picks up exactly the rows written after this pull.
+//
+// With `limit`, rows are returned table by table in `(updated_at, id)` order and
+// `meta.has_more` / `meta.next_cursor` say where to continue. Every page reads up to the
+// first page's watermark and reports it as `server_time`, so after the last page clients
+// store it as their next `since`; rows written in between come with that next pull.
+//
+// `tables` and `filters` narrow the pull for partial replication; unselected tables come
+// back empty. Send the same values with every page and every later `since`. A row that
+// stops matching a filter (e.g. a job reassigned to someone else) isn't sent again, and
+// rows that start matching only arrive once they change, so after changing its scope a
+// client should pull again without `since`. The caller's security profile (see `profiles`)
+// narrows the scope further, whatever the request asks for.
 pub async fn sync_handler(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Query(params): Query<SyncParams>,
 ) -> impl IntoResponse {
-    let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
-    let since = params.since.unwrap_or_else(|| "1970-01-01T00:00:00Z".to_string());
+    // `updated_at` holds HLC stamps, so `since` is compared in the same fixed-width format.
+    let Some(since) = hlc::normalize(params.since.as_deref().unwrap_or("1970-01-01T00:00:00Z")) else {
+        return (StatusCode::BAD_REQUEST, Json(json!({
+            "status": "error",
+            "error": "invalid_since",
+            "message": "since must be an RFC 3339 timestamp, e.g. a meta.server_time returned by GET /sync."
+        }))).into_response();
+    };
 
-    let conn = state.db.lock().unwrap();
+    let scope = match SyncScope::parse(params.tables.as_deref(), params.filters.as_deref()) {
+        Ok(scope) => scope,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(json!({
+                "status": "error",
+                "error": e.code(),
+                "message": e.to_string()
+            }))).into_response();
+        }
+    };
+
+    // The cursor is `server_time|table|updated_at|id`; see `TableCursor`.
+    let (watermark, after) = match params.cursor.as_deref().map(parse_sync_cursor) {
+        None => (None, None),
+        Some(Some((server_time, after))) if scope.includes(&after.table_name) => (Some(server_time), Some(after)),
+        Some(_) => {
+            return (StatusCode::BAD_REQUEST, Json(json!({
+                "status": "error",
+                "error": "invalid_cursor",
+                "message": "cursor must be a meta.next_cursor value returned by GET /sync."
+            }))).into_response();
+        }
+    };
+
+    let mut conn = state.db.lock().unwrap();
+
+    let tx = match conn.transaction() {
+        Ok(tx) => tx,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+    };
+
+    // The caller's security profile narrows whatever they asked for.
+    let scope = match pull_channel(&tx, &headers, &params.tenant_id) {
+        Ok(channel) => scope.restrict(&channel.scope),
+        Err(error) => return error.into_response(),
+    };
+
+    // The watermark and every table are read in one transaction, so the response is a single
+    // cut of the tenant's data: no write can land between two tables or after the watermark.
+    let snapshot = match watermark {
+        Some(watermark) => Ok(watermark),
+        None => fetching::watermark(&tx, &params.tenant_id, &since, data_result::SYNCED_TABLES),
+    }
+    .and_then(|watermark| {
+        let schema_version = schema_version::current(&tx, &params.tenant_id)?;
+        let mut page = DataPage::new(params.limit.map(|limit| limit.clamp(1, MAX_PAGE_SIZE)), after).up_to(&watermark).within(scope);
+        data_result::get_data_result(&tx, &params.tenant_id, &since, &mut page).map(|data| (watermark, schema_version, page, data))
+    });
 
-    let data_result = data_result::get_data_result(&conn, &params.tenant_id, &since);
+    // Nothing was written; dropping the transaction simply releases the read lock.
+    drop(tx);
 
-    match data_result {
-        Ok(data) => {
+    match snapshot {
+        Ok((watermark, schema_version, page, data)) => {
+            let next_cursor = page.next.as_ref().map(|next| format!("{}|{}", watermark, next.encode()));
             let response = SyncResponse {
                 meta: Meta {
-                    server_time: now,
+                    server_time: watermark,
                     since,
+                    schema_version,
+                    has_more: params.limit.map(|_| page.has_more()),
+                    next_cursor,
                 },
                 data,
             };
@@ -94,6 +176,14 @@ pub async fn sync_handler(
     }
 }
 
+/// Split a GET /sync cursor into the first page's `server_time` and the table position.
+fn parse_sync_cursor(cursor: &str) -> Option<(String, TableCursor)> {
+    let (server_time, position) = cursor.split_once('|')?;
+    let after = TableCursor::parse(position)?;
+    apply::synced_table_by_name(&after.table_name)?;
+    Some((server_time.to_string(), after))
+}
+
 // Struct for deserializing incoming overlay records from the client
 #[derive(Deserialize)]
 pub struct OverlayRecord {
@@ -101,10 +191,215 @@ pub struct OverlayRecord {
     pub tenant_id: String,
     pub object_id: String,
     pub object_name: String,
+    #[serde(default)]
+    pub operation: ChangeOperation, // Defaults to `update` (merge patch with upsert)
     pub changes: Value,
     pub created_at: String,
     pub state_hash: String, // The client-calculated hash for this change
     pub previous_state_hash: String, // The hash this change is based on
+    #[serde(default)]
+    pub expected_version: Option<i64>, // Reject with `version_conflict` unless the record is still at this version
+    #[serde(default)]
+    pub hash_version: Option<i64>, // Hash preimage version (see `HashVersion`); defaults to 1 (legacy)
+}
+
+// Query params for POST /sync
+#[derive(Deserialize)]
+pub struct PostSyncParams {
+    pub mode: Option<BatchMode>,
+    /// Re-chain overlays made against an older head on top of the current head,
+    /// as long as the changes they missed don't set the same fields (auto-merge).
+    /// On by default: rebased overlays get a server-computed `state_hash` (see
+    /// `rebased_from`). With `rebase=false` any divergence rejects the overlay whole.
+    pub rebase: Option<bool>,
+    /// The tenant `schema_version` the client's local database is at. Batches from clients
+    /// behind the tenant are rejected with `schema_upgrade_required`; omit it to skip the check.
+    pub schema_version: Option<i64>,
+}
+
+/// How a batch is committed when one of its overlays is rejected.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchMode {
+    /// All-or-nothing: any rejection rolls back the whole batch.
+    #[default]
+    Atomic,
+    /// Commit every overlay before the first rejection (the valid prefix).
+    Prefix,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlayStatus {
+    /// Appended to the change_log and applied; the client may delete its overlay.
+    Accepted,
+    /// Already in the change_log from an earlier request (e.g. a retry after a lost
+    /// response); nothing was written and the client may delete its overlay.
+    AlreadyApplied,
+    /// Failed validation or apply; see `error`.
+    Rejected,
+    /// Not committed because another overlay in the batch was rejected.
+    Skipped,
+}
+
+/// Outcome for one overlay, in request order.
+#[derive(Serialize)]
+pub struct OverlayResult {
+    pub id: String,
+    pub status: OverlayStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_hash: Option<String>,
+    /// The client's original `state_hash` when the server re-chained the overlay.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rebased_from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+impl OverlayResult {
+    fn accepted(change: &ChangeLogRecord) -> Self {
+        OverlayResult {
+            id: change.id.clone(),
+            status: OverlayStatus::Accepted,
+            sequence_id: Some(change.sequence_id),
+            state_hash: Some(change.state_hash.clone()),
+            rebased_from: None,
+            error: None,
+            message: None,
+            details: None,
+        }
+    }
+
+    fn already_applied(change: &ChangeLogRecord) -> Self {
+        OverlayResult {
+            status: OverlayStatus::AlreadyApplied,
+            ..OverlayResult::accepted(change)
+        }
+    }
+
+    fn rebased(change: &ChangeLogRecord, client_state_hash: &str) -> Self {
+        OverlayResult {
+            rebased_from: Some(client_state_hash.to_string()),
+            ..OverlayResult::accepted(change)
+        }
+    }
+
+    fn rejected(id: &str, rejection: &OverlayRejection) -> Self {
+        OverlayResult {
+            id: id.to_string(),
+            status: OverlayStatus::Rejected,
+            sequence_id: None,
+            state_hash: None,
+            rebased_from: None,
+            error: Some(rejection.error),
+            message: Some(rejection.message.clone()),
+            details: Some(rejection.details.clone()),
+        }
+    }
+
+    fn skipped(id: &str) -> Self {
+        OverlayResult {
+            id: id.to_string(),
+            status: OverlayStatus::Skipped,
+            sequence_id: None,
+            state_hash: None,
+            rebased_from: None,
+            error: None,
+            message: None,
+            details: None,
+        }
+    }
+}
+
+/// Response body for POST /sync.
+#[derive(Serialize)]
+pub struct PostSyncResponse {
+    /// "ok" when every overlay was accepted, "partial" when a prefix was committed,
+    /// "error" when nothing was committed.
+    pub status: &'static str,
+    /// The first rejection's error code and message, repeated for clients that only read the top level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    pub results: Vec<OverlayResult>,
+    /// The tenant's committed chain head after this request.
+    pub state_hash: String,
+    pub sequence_id: i64,
+    /// Changes between the client's anchor and the head that the client hasn't seen,
+    /// returned with a divergence or after a rebase so it can catch up without another pull.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing_changes: Vec<ChangeLogRecord>,
+}
+
+/// Why a single overlay was not accepted, with the HTTP status it maps to in atomic mode.
+struct OverlayRejection {
+    status: StatusCode,
+    error: &'static str,
+    message: String,
+    details: Value,
+    missing_changes: Vec<ChangeLogRecord>,
+}
+
+impl OverlayRejection {
+    fn new(status: StatusCode, error: &'static str, message: impl Into<String>, details: Value) -> Self {
+        OverlayRejection { status, error, message: message.into(), details, missing_changes: Vec::new() }
+    }
+
+    fn with_missing_changes(mut self, missing_changes: Vec<ChangeLogRecord>) -> Self {
+        self.missing_changes = missing_changes;
+        self
+    }
+}
+
+/// Tracks the chain head while a batch is processed, including overlays the
+/// server re-chained on top of changes the client hadn't seen.
+struct ChainCursor {
+    head: String,
+    rebase: bool,
+    /// Client `state_hash` → where that overlay now sits in the chain, so later
+    /// overlays in the batch that chain off the client hash follow it.
+    rebased: HashMap<String, RebasedLink>,
+    /// Ids appended by this batch; these are never reported back as missing.
+    batch_ids: HashSet<String>,
+}
+
+struct RebasedLink {
+    /// The `sequence_id` of the head the client originally built on.
+    anchor_sequence_id: i64,
+}
+
+impl ChainCursor {
+    /// Resolve the `sequence_id` a client hash was built on, following rebased siblings.
+    fn anchor_of(&self, conn: &Connection, tenant_id: &str, previous_state_hash: &str) -> rusqlite::Result<Option<i64>> {
+        match self.rebased.get(previous_state_hash) {
+            Some(link) => Ok(Some(link.anchor_sequence_id)),
+            None => change_log::find_sequence_id(conn, tenant_id, previous_state_hash),
+        }
+    }
+
+    /// Changes after `anchor_sequence_id` that didn't come from this batch.
+    fn missing_since(&self, conn: &Connection, tenant_id: &str, anchor_sequence_id: i64) -> rusqlite::Result<Vec<ChangeLogRecord>> {
+        let changes = change_log::fetch_changes_after(conn, tenant_id, anchor_sequence_id, None)?;
+        Ok(changes.into_iter().filter(|change| !self.batch_ids.contains(&change.id)).collect())
+    }
+}
+
+/// A successfully appended overlay, with how it was re-chained if it was.
+struct ProcessedOverlay {
+    change: ChangeLogRecord,
+    rebase: Option<Rebase>,
+}
+
+struct Rebase {
+    anchor_sequence_id: i64,
+    missing_changes: Vec<ChangeLogRecord>,
 }
 
 /// Handler for POST /sync
@@ -120,8 +415,31 @@ pub struct OverlayRecord {
 ///   `state_hash`. This must match the client's `state_hash`.
 /// - On success, the server appends the row, updates the domain record, and
 ///   advances the head; processing continues for the next item in the batch.
+/// - Once the batch commits, the appended rows are broadcast to the tenant's
+///   live `/ws` subscribers.
+///
+/// The response lists a result per overlay (accepted, rejected with reason, or
+/// skipped) plus the resulting chain head. By default the batch is atomic;
+/// with `?mode=prefix` the overlays before the first rejection are committed.
+/// Items after a rejection are always skipped, since they chain off it.
+/// Overlays whose `id` and `state_hash` are already in the change_log are
+/// reported as `already_applied`, so retrying after a lost response is safe.
+///
+/// An overlay's base is the head it was made against (`previous_state_hash`).
+/// When that is older than the current head, the server auto-merges: it
+/// re-chains the overlay on top of the head (recomputing `state_hash`) unless
+/// one of the changes it missed set the same field of the same record to a
+/// different value. Such overlays are rejected with `field_conflict` and a list
+/// of `{ field, base, theirs, mine }` so the client can ask the user. With
+/// `?rebase=false` any divergence is rejected with `chain_diverged` instead.
+/// Either way the response carries the `missing_changes` since the anchor.
+///
+/// `missing_changes` only list records the caller's security profile lets them
+/// pull. A conflict on a record outside it is rejected with `record_not_visible`
+/// instead, without the server's values.
 pub async fn post_sync_handler(
     State(state): State<AppState>,
+    Query(params): Query<PostSyncParams>,
     headers: HeaderMap,
     Json(overlays): Json<Vec<OverlayRecord>>,
 ) -> impl IntoResponse {
@@ -130,6 +448,9 @@ pub async fn post_sync_handler(
         return (StatusCode::OK, Json(json!({ "status": "ok", "message": "No changes to sync." }))).into_response();
     }
 
+    let mode = params.mode.unwrap_or_default();
+    let rebase = params.rebase.unwrap_or(true);
+
     // Serialize access to the DB (SQLite) with a mutex guard.
     let mut conn = state.db.lock().unwrap();
 
@@ -140,7 +461,7 @@ pub async fn post_sync_handler(
         .unwrap_or("fake_user_from_header"); // Default for development
 
     // Start a transaction to ensure atomicity of the batch.
-    let tx = match conn.transaction() {
+    let mut tx = match conn.transaction() {
         Ok(tx) => tx,
         Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
     };
@@ -180,122 +501,500 @@ pub async fn post_sync_handler(
         }
     }
 
-    // --- Batch Validation Step 1: Verify the chain's starting point ---
-    // Get the server's latest hash just once for this tenant.
-    let server_latest_hash_result: Result<String, _> = tx.query_row(
-        "SELECT state_hash FROM change_log WHERE tenant_id = ?1 ORDER BY sequence_id DESC LIMIT 1",
-        params![&overlays[0].tenant_id],
-        |row| row.get(0),
-    );
-    let mut current_chain_head = server_latest_hash_result.unwrap_or(GENESIS_HASH.to_string());
-
-    for overlay in overlays {
-        // Optionally constrain which objects are handled. Others are currently skipped.
-        if overlay.object_name != "job" { continue; }
-
-        // --- Validation Step 2: Verify each link in the chain ---
-        if overlay.previous_state_hash != current_chain_head {
+    // Changes written against an older schema may use fields that no longer mean the same
+    // thing, so the client must upgrade (and re-pull) before its pushes are accepted.
+    if let Some(client_schema_version) = params.schema_version {
+        let schema_version = match schema_version::current(&tx, &overlays[0].tenant_id) {
+            Ok(schema_version) => schema_version,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+        };
+        if client_schema_version < schema_version {
             return (StatusCode::CONFLICT, Json(json!({
                 "status": "error",
-                "message": "Client history has diverged or batch is inconsistent. Please sync first."
+                "error": "schema_upgrade_required",
+                "message": "The tenant's schema changed. Upgrade the local schema and pull before pushing again.",
+                "details": { "schema_version": schema_version, "client_schema_version": client_schema_version }
             }))).into_response();
         }
+    }
 
-        // --- Validation Step 3: Verify the Content ---
-        // Serialize the JSON as a minified string (serde_json's to_string). This must match the
-        // client's canonicalization strategy.
-        let changes_json = overlay.changes.to_string();
-
-        // Recompute client content hash.
-        let change_to_hash = format!(
-            "{}{}{}{}{}{}{}",
-            overlay.id,
-            overlay.tenant_id,
-            user_id, // Using the user_id from the header
-            overlay.created_at,
-            overlay.object_name,
-            overlay.object_id,
-            changes_json
-        );
-        let mut hasher = Sha256::new();
-        hasher.update(change_to_hash.as_bytes());
-        let change_hash = format!("{:x}", hasher.finalize());
-        // Combine with previous_state_hash to compute the new state hash.
-        let combined_hash_data = format!("{}{}", change_hash, overlay.previous_state_hash);
-        let mut final_hasher = Sha256::new();
-        final_hasher.update(combined_hash_data.as_bytes());
-        let server_calculated_hash = format!("{:x}", final_hasher.finalize());
-
-        if server_calculated_hash != overlay.state_hash {
-            // Provide detailed mismatch context for debugging client/server hashing.
-            return (StatusCode::BAD_REQUEST, Json(json!({
-                "status": "error",
-                "error": "hash_mismatch",
-                "message": "Client hash does not match server calculation.",
-                "details": {
-                    "tenant_id": overlay.tenant_id,
-                    "object_name": overlay.object_name,
-                    "object_id": overlay.object_id,
-                    "created_at": overlay.created_at,
-                    "user_id": user_id,
-                    "previous_state_hash": overlay.previous_state_hash,
-                    "client_state_hash": overlay.state_hash,
-                    "server_state_hash": server_calculated_hash,
-                    "server_change_hash": change_hash,
-                    // Echo back the exact JSON string we hashed on the server side
-                    "server_changes_json": changes_json,
-                }
-            }))).into_response();
+    // --- Batch Validation Step 1: Verify the chain's starting point ---
+    // Get the server's latest head just once for this tenant.
+    let tenant_id = overlays[0].tenant_id.clone();
+    // Missing changes and conflicts reveal records, so they go through the caller's channel like a pull.
+    let channel = match pull_channel(&tx, &headers, &tenant_id) {
+        Ok(channel) => channel,
+        Err(error) => return error.into_response(),
+    };
+    let (initial_sequence_id, initial_state_hash) = match change_log::chain_head(&tx, &tenant_id) {
+        Ok(head) => head,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+    };
+    let mut cursor = ChainCursor {
+        head: initial_state_hash.clone(),
+        rebase,
+        rebased: HashMap::new(),
+        batch_ids: HashSet::new(),
+    };
+
+    // Rows appended by this batch, read back after insert so subscribers get the stored shape.
+    let mut appended: Vec<ChangeLogRecord> = Vec::new();
+    let mut results: Vec<OverlayResult> = Vec::with_capacity(overlays.len());
+    let mut first_rejection: Option<OverlayRejection> = None;
+    // Foreign changes the rebased overlays were re-chained over, keyed by sequence_id.
+    let mut missing_changes: BTreeMap<i64, ChangeLogRecord> = BTreeMap::new();
+
+    for overlay in &overlays {
+        if first_rejection.is_some() {
+            results.push(OverlayResult::skipped(&overlay.id));
+            continue;
         }
-        // --- End of Validation ---
-
-        // --- Persist the Change ---
-        // Note: we do not insert the sequence_id, it's an auto-incrementing primary key.
-        let change_log_result = tx.execute(
-            "INSERT INTO change_log (id, tenant_id, user_id, object_name, record_id, change_data, state_hash, previous_state_hash, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-            params![
-                &overlay.id,
-                &overlay.tenant_id,
-                user_id,
-                &overlay.object_name,
-                &overlay.object_id,
-                &changes_json,
-                &overlay.state_hash, // Persist the verified hash from the client
-                &overlay.previous_state_hash,
-                &overlay.created_at,
-            ],
-        );
-        
-        if let Err(e) = change_log_result {
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
-                "status": "error",
-                "error": "change_log_insert_failed",
-                "message": "Failed to insert into change_log.",
-                "details": { "sqlite_error": e.to_string() }
-            }))).into_response();
+
+        // Idempotent replay: an overlay we already appended is reported, not re-applied.
+        match replayed_change(&tx, overlay) {
+            Ok(Some(change)) => {
+                if change.state_hash != overlay.state_hash {
+                    // It was rebased the first time; later overlays in this retry chain off the client hash.
+                    let anchor_sequence_id = cursor
+                        .anchor_of(&tx, &tenant_id, &overlay.previous_state_hash)
+                        .ok()
+                        .flatten()
+                        .unwrap_or(change.sequence_id - 1);
+                    cursor.rebased.insert(overlay.state_hash.clone(), RebasedLink { anchor_sequence_id });
+                }
+                results.push(OverlayResult::already_applied(&change));
+                continue;
+            }
+            Ok(None) => {}
+            Err(rejection) => {
+                results.push(OverlayResult::rejected(&overlay.id, &rejection));
+                first_rejection = Some(rejection);
+                continue;
+            }
         }
 
-        // Apply the change to the jobs table (upsert semantics for client-generated IDs).
-        if let Err(e) = apply_job_change(&tx, &overlay, user_id, &changes_json) {
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
-                "status": "error",
-                "error": "domain_apply_failed",
-                "message": "Failed to apply change to domain table.",
-                "details": { "sqlite_error": e.to_string(), "object_name": overlay.object_name }
-            }))).into_response();
+        // Each overlay runs in its own savepoint so a failed item leaves no partial writes behind.
+        let savepoint = match tx.savepoint() {
+            Ok(savepoint) => savepoint,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+        };
+        let outcome = process_overlay(&savepoint, overlay, &tenant_id, user_id, &channel, &cursor)
+            .and_then(|processed| {
+                savepoint
+                    .commit()
+                    .map(|_| processed)
+                    .map_err(|e| OverlayRejection::new(StatusCode::INTERNAL_SERVER_ERROR, "savepoint_failed", e.to_string(), json!({})))
+            });
+
+        match outcome {
+            Ok(ProcessedOverlay { change, rebase }) => {
+                // --- Update the head of the chain for the next iteration ---
+                cursor.head = change.state_hash.clone();
+                cursor.batch_ids.insert(change.id.clone());
+                match rebase {
+                    Some(rebase) => {
+                        cursor.rebased.insert(overlay.state_hash.clone(), RebasedLink { anchor_sequence_id: rebase.anchor_sequence_id });
+                        missing_changes.extend(rebase.missing_changes.into_iter().map(|missing| (missing.sequence_id, missing)));
+                        results.push(OverlayResult::rebased(&change, &overlay.state_hash));
+                    }
+                    None => results.push(OverlayResult::accepted(&change)),
+                }
+                appended.push(change);
+            }
+            Err(rejection) => {
+                results.push(OverlayResult::rejected(&overlay.id, &rejection));
+                first_rejection = Some(rejection);
+            }
         }
+    }
 
-        // --- Update the head of the chain for the next iteration ---
-        current_chain_head = overlay.state_hash.clone();
+    // Atomic mode: a rejection discards the whole batch. Dropping `tx` rolls it back.
+    if let (BatchMode::Atomic, Some(rejection)) = (mode, &first_rejection) {
+        for result in results.iter_mut().filter(|result| result.status == OverlayStatus::Accepted) {
+            *result = OverlayResult::skipped(&result.id);
+        }
+        return (rejection.status, Json(PostSyncResponse {
+            status: "error",
+            error: Some(rejection.error),
+            message: Some(rejection.message.clone()),
+            results,
+            state_hash: initial_state_hash,
+            sequence_id: initial_sequence_id,
+            missing_changes: rejection.missing_changes.clone(),
+        })).into_response();
     }
 
     if let Err(e) = tx.commit() {
         return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response();
     }
 
-    (StatusCode::OK, Json(json!({ "status": "ok" }))).into_response()
+    let (head_sequence_id, head_state_hash) = match appended.last() {
+        Some(change) => (change.sequence_id, change.state_hash.clone()),
+        None => (initial_sequence_id, initial_state_hash),
+    };
+
+    // Only committed rows are visible to subscribers.
+    state.broadcaster.publish(&conn, &tenant_id, appended);
+
+    let status = match (&first_rejection, head_sequence_id == initial_sequence_id) {
+        (None, _) => "ok",
+        (Some(_), true) => "error",
+        (Some(_), false) => "partial",
+    };
+    if let Some(rejection) = &first_rejection {
+        missing_changes.extend(rejection.missing_changes.iter().map(|missing| (missing.sequence_id, missing.clone())));
+    }
+    (StatusCode::OK, Json(PostSyncResponse {
+        status,
+        error: first_rejection.as_ref().map(|rejection| rejection.error),
+        message: first_rejection.map(|rejection| rejection.message),
+        results,
+        state_hash: head_state_hash,
+        sequence_id: head_sequence_id,
+        missing_changes: missing_changes.into_values().collect(),
+    })).into_response()
+}
+
+/// Look up an overlay that was already appended to the change_log by an earlier request.
+/// The id must match with the same `state_hash`, or with the same content when the
+/// server rebased it; the same id with different content is rejected.
+fn replayed_change(conn: &Connection, overlay: &OverlayRecord) -> Result<Option<ChangeLogRecord>, OverlayRejection> {
+    let existing = change_log::find_change(conn, &overlay.id).map_err(|e| OverlayRejection::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "change_log_read_failed",
+        "Failed to look up existing change.",
+        json!({ "sqlite_error": e.to_string() }),
+    ))?;
+
+    match existing {
+        Some(change) if change.tenant_id == overlay.tenant_id && change.state_hash == overlay.state_hash => Ok(Some(change)),
+        Some(change) if change.tenant_id == overlay.tenant_id
+            && change.object_name == overlay.object_name
+            && change.record_id == overlay.object_id
+            && change.operation == overlay.operation
+            && change.created_at == overlay.created_at
+            && change.change_data == overlay.changes => Ok(Some(change)),
+        Some(change) => Err(OverlayRejection::new(
+            StatusCode::CONFLICT,
+            "duplicate_id",
+            "A different change with this id already exists.",
+            json!({ "id": overlay.id, "client_state_hash": overlay.state_hash, "server_state_hash": change.state_hash }),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Validate one overlay against the current chain head, append it to the
+/// change_log and apply it to its domain table.
+fn process_overlay(
+    conn: &Connection,
+    overlay: &OverlayRecord,
+    tenant_id: &str,
+    user_id: &str,
+    channel: &Channel,
+    cursor: &ChainCursor,
+) -> Result<ProcessedOverlay, OverlayRejection> {
+    // A batch extends a single tenant's chain.
+    if overlay.tenant_id != tenant_id {
+        return Err(OverlayRejection::new(
+            StatusCode::BAD_REQUEST,
+            "tenant_mismatch",
+            "All overlays in a batch must belong to the same tenant.",
+            json!({ "tenant_id": overlay.tenant_id, "batch_tenant_id": tenant_id }),
+        ));
+    }
+
+    // Resolve the domain table up front so unknown object types fail loudly instead of being dropped.
+    let Some(table) = apply::synced_table(&overlay.object_name) else {
+        return Err(OverlayRejection::new(
+            StatusCode::BAD_REQUEST,
+            "unknown_object_name",
+            "Provided object_name does not map to a synced table.",
+            json!({ "object_name": overlay.object_name, "object_id": overlay.object_id }),
+        ));
+    };
+
+    // --- Validation Step 2: Verify the Content ---
+    // The client says how it built the hash preimage: version 3 is one RFC 8785 (JCS)
+    // canonical array of the hashed fields; version 2 concatenates the fields with JCS
+    // `changes`; version 1 concatenates them with serde_json's minified text. 1 and 2 are
+    // accepted during the migration to 3 (see `ACCEPTED_HASH_VERSIONS`).
+    let requested_version = overlay.hash_version.unwrap_or(HashVersion::Legacy.as_i64());
+    let Some(hash_version) = HashVersion::from_i64(requested_version).filter(|version| ACCEPTED_HASH_VERSIONS.contains(version)) else {
+        return Err(OverlayRejection::new(
+            StatusCode::BAD_REQUEST,
+            "unsupported_hash_version",
+            "Provided hash_version is not accepted by this server.",
+            json!({ "hash_version": requested_version, "supported": ACCEPTED_HASH_VERSIONS }),
+        ));
+    };
+    let changes_json = hash_version.changes_text(&overlay.changes);
+
+    // Recompute the client's hash against the head the client built on.
+    let change_hash = hashing::change_hash(hash_version, &ChangeHashInput {
+        id: &overlay.id,
+        tenant_id: &overlay.tenant_id,
+        user_id, // Using the user_id from the header
+        created_at: &overlay.created_at,
+        object_name: &overlay.object_name,
+        record_id: &overlay.object_id,
+        operation: overlay.operation,
+        changes: &overlay.changes,
+    });
+    let server_calculated_hash = hashing::chain_state_hash(&change_hash, &overlay.previous_state_hash);
+
+    if server_calculated_hash != overlay.state_hash {
+        // Provide detailed mismatch context for debugging client/server hashing.
+        return Err(OverlayRejection::new(
+            StatusCode::BAD_REQUEST,
+            "hash_mismatch",
+            "Client hash does not match server calculation.",
+            json!({
+                "tenant_id": overlay.tenant_id,
+                "object_name": overlay.object_name,
+                "object_id": overlay.object_id,
+                "created_at": overlay.created_at,
+                "user_id": user_id,
+                "previous_state_hash": overlay.previous_state_hash,
+                "client_state_hash": overlay.state_hash,
+                "server_state_hash": server_calculated_hash,
+                "server_change_hash": change_hash,
+                "hash_version": hash_version,
+                // Echo back the exact JSON string we hashed on the server side
+                "server_changes_json": changes_json,
+            }),
+        ));
+    }
+
+    // --- Validation Step 3: Verify each link in the chain ---
+    let mut rebase: Option<Rebase> = None;
+    if overlay.previous_state_hash != cursor.head {
+        let read_failed = |e: rusqlite::Error| OverlayRejection::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "change_log_read_failed",
+            "Failed to read the change_log.",
+            json!({ "sqlite_error": e.to_string() }),
+        );
+
+        let Some(anchor_sequence_id) = cursor.anchor_of(conn, tenant_id, &overlay.previous_state_hash).map_err(read_failed)? else {
+            return Err(OverlayRejection::new(
+                StatusCode::CONFLICT,
+                "chain_diverged",
+                "Client history has diverged and its anchor is unknown. Please bootstrap.",
+                json!({ "previous_state_hash": overlay.previous_state_hash, "server_state_hash": cursor.head, "bootstrap_required": true }),
+            ));
+        };
+        let missing = cursor.missing_since(conn, tenant_id, anchor_sequence_id).map_err(read_failed)?;
+        // Merging considers every missed change; the client is only shown those it may pull.
+        let visible = channel.visible(conn, tenant_id, missing.clone()).map_err(read_failed)?;
+
+        if !cursor.rebase {
+            return Err(OverlayRejection::new(
+                StatusCode::CONFLICT,
+                "chain_diverged",
+                "Client history has diverged or batch is inconsistent. Apply missing_changes and retry, or retry without rebase=false to let the server merge.",
+                json!({ "previous_state_hash": overlay.previous_state_hash, "server_state_hash": cursor.head }),
+            ).with_missing_changes(visible));
+        }
+
+        // Auto-merge: rebase unless a missed change set one of the same fields to a different value.
+        let unseen: Vec<&ChangeLogRecord> = missing
+            .iter()
+            .filter(|other| other.object_name == overlay.object_name && other.record_id == overlay.object_id)
+            .collect();
+        if !unseen.is_empty() {
+            // The base is the record as of the client's anchor; theirs is the record as it stands now.
+            // Anchors resolve only at or after the compaction anchor, so the state is always rebuildable.
+            let base = compaction::record_state_at(conn, tenant_id, &overlay.object_name, &overlay.object_id, Some(anchor_sequence_id))
+                .map_err(read_failed)?
+                .and_then(|record| record.document().cloned());
+            let theirs = apply::fetch_document(conn, table, tenant_id, &overlay.object_id).map_err(read_failed)?;
+            let conflicts = merge::field_conflicts(overlay.operation, &overlay.changes, unseen, base.as_ref(), theirs.as_ref());
+            if !conflicts.is_empty() {
+                // Conflicts carry the record's current values; callers outside its channel only learn that it changed.
+                if !channel.scope.allows(conn, tenant_id, &overlay.object_name, &overlay.object_id).map_err(read_failed)? {
+                    return Err(OverlayRejection::new(
+                        StatusCode::CONFLICT,
+                        "record_not_visible",
+                        "Changes the client hasn't seen modify the same fields of a record outside its security profile.",
+                        json!({ "previous_state_hash": overlay.previous_state_hash, "server_state_hash": cursor.head, "object_id": overlay.object_id }),
+                    ).with_missing_changes(visible));
+                }
+                return Err(OverlayRejection::new(
+                    StatusCode::CONFLICT,
+                    "field_conflict",
+                    "Changes the client hasn't seen modify the same fields.",
+                    json!({ "previous_state_hash": overlay.previous_state_hash, "server_state_hash": cursor.head, "conflicts": conflicts }),
+                ).with_missing_changes(visible));
+            }
+        }
+
+        rebase = Some(Rebase { anchor_sequence_id, missing_changes: visible });
+    }
+
+    // A rebased overlay is re-chained onto the current head with a server-computed hash.
+    let (state_hash, previous_state_hash) = match rebase {
+        Some(_) => (hashing::chain_state_hash(&change_hash, &cursor.head), cursor.head.clone()),
+        None => (overlay.state_hash.clone(), overlay.previous_state_hash.clone()),
+    };
+    // --- End of Validation ---
+
+    // --- Persist the Change ---
+    // The client's `created_at` is kept as hashed; the server's HLC stamp orders the change.
+    let stamp = hlc::next_stamp(conn, tenant_id).map_err(|e| OverlayRejection::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "change_log_read_failed",
+        "Failed to read the change_log.",
+        json!({ "sqlite_error": e.to_string() }),
+    ))?;
+    // Metadata edits move the tenant to a new schema version, which the change is tagged with.
+    let schema_version = schema_version::for_change(conn, tenant_id, &overlay.object_name).map_err(|e| OverlayRejection::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "schema_version_failed",
+        "Failed to update the tenant's schema version.",
+        json!({ "sqlite_error": e.to_string() }),
+    ))?;
+    // Note: we do not insert the sequence_id, it's an auto-incrementing primary key.
+    conn.execute(
+        "INSERT INTO change_log (id, tenant_id, user_id, object_name, record_id, change_data, state_hash, previous_state_hash, created_at, operation, hash_version, hlc, schema_version) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        params![
+            &overlay.id,
+            &overlay.tenant_id,
+            user_id,
+            &overlay.object_name,
+            &overlay.object_id,
+            &changes_json,
+            &state_hash, // Persist the verified hash (the client's, unless rebased)
+            &previous_state_hash,
+            &overlay.created_at,
+            overlay.operation.as_str(),
+            hash_version.as_i64(),
+            &stamp,
+            schema_version,
+        ],
+    )
+    .map_err(|e| OverlayRejection::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "change_log_insert_failed",
+        "Failed to insert into change_log.",
+        json!({ "sqlite_error": e.to_string() }),
+    ))?;
+
+    // Apply the change to its domain table (upsert semantics for client-generated IDs).
+    let record_change = RecordChange {
+        tenant_id: &overlay.tenant_id,
+        object_name: &overlay.object_name,
+        record_id: &overlay.object_id,
+        user_id,
+        operation: overlay.operation,
+        change_data: &changes_json,
+        timestamp: &overlay.created_at,
+        stamped_at: &stamp,
+        expected_version: overlay.expected_version,
+    };
+    apply::apply_change(conn, table, &record_change).map_err(|e| {
+        let status = match e {
+            ApplyError::RecordExists | ApplyError::VersionConflict { .. } => StatusCode::CONFLICT,
+            ApplyError::ForeignRecord => StatusCode::FORBIDDEN,
+            ApplyError::UnknownObjectName | ApplyError::DeleteUnsupported => StatusCode::BAD_REQUEST,
+            ApplyError::InvalidDocument(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApplyError::Sql(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let mut details = json!({ "reason": e.to_string(), "object_name": overlay.object_name, "object_id": overlay.object_id, "operation": overlay.operation });
+        if let ApplyError::VersionConflict { expected, current } = e {
+            details["expected_version"] = json!(expected);
+            details["current_version"] = json!(current);
+        }
+        OverlayRejection::new(status, e.code(), "Failed to apply change to domain table.", details)
+    })?;
+
+    let change = change_log::fetch_change(conn, &overlay.id).map_err(|e| OverlayRejection::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "change_log_read_failed",
+        "Failed to read back the appended change.",
+        json!({ "sqlite_error": e.to_string() }),
+    ))?;
+    Ok(ProcessedOverlay { change, rebase })
+}
+
+// --- Server-authored Changes ---
+
+/// A change made by server code rather than a client: admin tooling, imports, automation.
+pub struct ServerChange<'a> {
+    pub object_name: &'a str,
+    pub record_id: &'a str,
+    pub operation: ChangeOperation,
+    /// The JSON merge patch applied to the record's `data` document.
+    pub changes: &'a Value,
+    /// As on overlays: only apply if the record's stored `version` still equals it.
+    pub expected_version: Option<i64>,
+}
+
+/// A transaction for appending server-authored changes to one tenant's chain.
+///
+/// Each change is hashed onto the head as read inside the transaction, so nothing can
+/// land in between, and applied to its domain table like a client change. `commit`
+/// returns the appended rows for the caller to broadcast once the connection is free
+/// again; dropping the batch rolls it back.
+pub struct ServerChanges<'conn> {
+    tx: rusqlite::Transaction<'conn>,
+    tenant_id: String,
+    appended: Vec<ChangeLogRecord>,
 }
 
+impl<'conn> ServerChanges<'conn> {
+    pub fn begin(conn: &'conn mut Connection, tenant_id: &str) -> Result<Self> {
+        let tx = conn.transaction()?;
+        Ok(ServerChanges { tx, tenant_id: tenant_id.to_string(), appended: Vec::new() })
+    }
+
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    /// The transaction, for reads that must see the changes appended so far.
+    pub fn conn(&self) -> &Connection {
+        &self.tx
+    }
+
+    /// Append one change authored by `user_id` and apply it. A change that fails leaves
+    /// nothing behind, and the batch can go on.
+    pub fn append(&mut self, user_id: &str, change: &ServerChange) -> std::result::Result<ChangeLogRecord, ApplyError> {
+        let table = apply::synced_table(change.object_name).ok_or(ApplyError::UnknownObjectName)?;
+        let savepoint = self.tx.savepoint()?;
+        let created_at = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        let appended = change_log::append_change(&savepoint, &NewChange {
+            tenant_id: &self.tenant_id,
+            user_id,
+            object_name: change.object_name,
+            record_id: change.record_id,
+            operation: change.operation,
+            changes: change.changes,
+            created_at: &created_at,
+        })?;
+        apply::apply_change(&savepoint, table, &RecordChange {
+            tenant_id: &self.tenant_id,
+            object_name: change.object_name,
+            record_id: change.record_id,
+            user_id,
+            operation: change.operation,
+            change_data: &change.changes.to_string(),
+            timestamp: &created_at,
+            stamped_at: appended.stamped_at(),
+            expected_version: change.expected_version,
+        })?;
+        savepoint.commit()?;
+        self.appended.push(appended.clone());
+        Ok(appended)
+    }
+
+    /// Commit the batch. Returns the appended rows in chain order, to pass to
+    /// `ChangeBroadcaster::publish`.
+    pub fn commit(self) -> Result<Vec<ChangeLogRecord>> {
+        self.tx.commit()?;
+        Ok(self.appended)
+    }
+}
 
 // --- V2 Sync Pull ---
 
@@ -303,96 +1002,111 @@ pub async fn post_sync_handler(
 pub struct SyncParamsV2 {
     pub tenant_id: String,
     pub since_hash: Option<String>,
+    /// Maximum number of changes to return; omit to get everything after the anchor.
+    pub limit: Option<usize>,
 }
 
+/// The body of a GET /sync/v2 request with `limit`. Without it the response stays the
+/// bare array of changes it always was.
 #[derive(Serialize)]
-pub struct ChangeLogRecord {
-    pub sequence_id: i64,
-    pub id: String,
-    pub tenant_id: String,
-    pub user_id: String,
-    pub object_name: String,
-    pub record_id: String,
-    pub change_data: Value,
-    pub state_hash: String,
-    pub previous_state_hash: String,
-    pub created_at: String,
+pub struct SyncResponseV2 {
+    pub changes: Vec<ChangeLogRecord>,
+    /// True when `limit` cut the response short; pull again from `next_since_hash`.
+    pub has_more: bool,
+    /// The `state_hash` of the last change returned (or the request's `since_hash`
+    /// when there were none). It is the cursor for the next page.
+    pub next_since_hash: String,
+    /// The latest signed checkpoint covering this page (at or before its last change),
+    /// so clients can confirm the chain they hold matches a head the server signed.
+    pub checkpoint: Option<Checkpoint>,
 }
 
 // V2 delta pull endpoint for a hash‑chained, append‑only change log.
 //
 // High‑level:
 // - Clients track the last applied `state_hash` and pass it as `since_hash`.
+// - Anchors older than the latest change_log compaction can't be served any more;
+//   those clients get `bootstrap_required` with the `snapshot_id` that replaced them.
 // - We resolve that hash to a monotonic `sequence_id` and return all rows with
 //   `sequence_id > anchor` for this tenant, ordered ASC.
 // - Each row carries `state_hash` and `previous_state_hash` so the client can
 //   verify the hash chain while applying changes.
+// - Each row carries its `operation`; `delete` rows are tombstones for `record_id`.
+// - Without `limit` the body is a JSON array of every change after the anchor.
+// - With `limit` the body is a `SyncResponseV2` holding at most that many rows. The
+//   chain itself is the cursor: clients pass `next_since_hash` as `since_hash` while
+//   `has_more` is true. Paged responses also carry the latest signed checkpoint.
+// - Callers with a security profile only get changes to records it allows, judged
+//   by the records' current rows. `limit` counts the rows scanned, so a page may hold
+//   fewer changes; and with rows left out the chain has gaps, so such clients can't
+//   check `previous_state_hash` links between the changes they receive.
 pub async fn sync_handler_v2(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Query(params): Query<SyncParamsV2>,
 ) -> impl IntoResponse {
     // Get a connection guard. SQLite is used behind a Mutex for safe access.
     let conn = state.db.lock().unwrap();
 
+    let channel = match pull_channel(&conn, &headers, &params.tenant_id) {
+        Ok(channel) => channel,
+        Err(error) => return error.into_response(),
+    };
+
     // Require an anchor hash. `Option` pattern‑match: if None, return 400.
     let Some(since_hash) = params.since_hash else {
         return (StatusCode::BAD_REQUEST, Json(json!({
             "error": "bootstrap_required",
-            "message": "No since_hash provided. New clients must use the bootstrap endpoint."
+            "message": "No since_hash provided. New clients must use the bootstrap endpoint (GET /sync/bootstrap)."
         }))).into_response();
     };
 
     // Find the anchor `sequence_id` for (tenant_id, since_hash).
-    // `query_row` returns a `Result<T, rusqlite::Error>`.
-    let since_sequence_id_result: Result<i64, _> = conn.query_row(
-        "SELECT sequence_id FROM change_log WHERE state_hash = ?1 AND tenant_id = ?2",
-        params![&since_hash, &params.tenant_id],
-        |row| row.get(0),
-    );
+    let since_sequence_id_result = change_log::find_sequence_id(&conn, &params.tenant_id, &since_hash);
 
     // Require a successful lookup. If the hash is unknown, ask the client to bootstrap.
-    let Ok(since_sequence_id) = since_sequence_id_result else {
+    // When the anchor was compacted away, say which snapshot replaced it.
+    let Ok(Some(since_sequence_id)) = since_sequence_id_result else {
+        let snapshot = compaction::latest_snapshot(&conn, &params.tenant_id).ok().flatten();
         return (StatusCode::BAD_REQUEST, Json(json!({
             "error": "bootstrap_required",
-            "message": "Provided since_hash not found. Client may be too old and must perform a new bootstrap sync."
+            "message": "Provided since_hash not found. Client may be too old and must perform a new bootstrap sync.",
+            "snapshot_id": snapshot.map(|snapshot| snapshot.id),
         }))).into_response();
     };
 
-    // Map a result row to our API model. `row.get::<_, T>(index)` extracts a typed column by index.
-    let map_change_row = |row: &rusqlite::Row| -> rusqlite::Result<ChangeLogRecord> {
-        let change_data_str: String = row.get(6)?;
-        Ok(ChangeLogRecord {
-            sequence_id: row.get(0)?,
-            id: row.get(1)?,
-            tenant_id: row.get(2)?,
-            user_id: row.get(3)?,
-            object_name: row.get(4)?,
-            record_id: row.get(5)?,
-            // Parse JSON payload; if parsing fails, use JSON null to keep the stream resilient.
-            change_data: serde_json::from_str(&change_data_str).unwrap_or(json!(null)),
-            state_hash: row.get(7)?,
-            previous_state_hash: row.get(8)?,
-            created_at: row.get(9)?,
-        })
-    };
-
-    // Prepare and execute the delta query:
+    // Execute the delta query:
     // - Only rows for this tenant
     // - Strictly after the anchor (sequence_id > since_sequence_id)
     // - Ordered ASC for safe sequential application
-    let mut stmt = conn.prepare(
-        "SELECT sequence_id, id, tenant_id, user_id, object_name, record_id, change_data, state_hash, previous_state_hash, created_at FROM change_log WHERE tenant_id = ?1 AND sequence_id > ?2 ORDER BY sequence_id ASC"
-    ).unwrap();
-
-    // Execute and map rows. We avoid the `?` operator inside async by chaining `and_then`.
-    let changes_result: Result<Vec<ChangeLogRecord>, rusqlite::Error> = stmt
-        .query_map(params![params.tenant_id, since_sequence_id], map_change_row)
-        .and_then(|rows| rows.collect());
+    // Fetch one extra row to learn whether there is another page.
+    let limit = params.limit.map(|limit| limit.clamp(1, MAX_PAGE_SIZE));
+    let changes_result = change_log::fetch_changes_after(&conn, &params.tenant_id, since_sequence_id, limit.map(|limit| limit + 1));
 
     match changes_result {
-        // Success → 200 with the list of deltas.
-        Ok(changes) => {
-            (StatusCode::OK, Json(changes)).into_response()
+        // Success → 200 with every delta, as a bare array for clients that don't page.
+        Ok(changes) if limit.is_none() => match channel.visible(&conn, &params.tenant_id, changes) {
+            Ok(changes) => (StatusCode::OK, Json(changes)).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+        },
+        // Success → 200 with the page of deltas and where to continue.
+        Ok(mut changes) => {
+            let has_more = limit.is_some_and(|limit| changes.len() > limit);
+            if let Some(limit) = limit {
+                changes.truncate(limit);
+            }
+            // Continue after the last row scanned, even when the channel doesn't see it.
+            let next_since_hash = changes.last().map_or(since_hash, |last| last.state_hash.clone());
+            let last_sequence_id = changes.last().map_or(since_sequence_id, |last| last.sequence_id);
+            let changes = match channel.visible(&conn, &params.tenant_id, changes) {
+                Ok(changes) => changes,
+                Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+            };
+            let checkpoint = match checkpoint::latest_checkpoint(&conn, &params.tenant_id, Some(last_sequence_id)) {
+                Ok(checkpoint) => checkpoint,
+                Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+            };
+            (StatusCode::OK, Json(SyncResponseV2 { changes, has_more, next_since_hash, checkpoint })).into_response()
         }
         // SQL/mapping error → 500 with a simple message.
         Err(e) => {
@@ -400,3 +1114,82 @@ pub async fn sync_handler_v2(
         }
     }
 }
+
+
+// --- Bootstrap ---
+
+#[derive(Deserialize)]
+pub struct BootstrapParams {
+    pub tenant_id: String,
+}
+
+#[derive(Serialize)]
+pub struct BootstrapResponse {
+    pub meta: Meta,
+    /// The `change_log` head the snapshot corresponds to. Clients store this as
+    /// their anchor and pass it as `since_hash` to `GET /sync/v2` or `/ws`.
+    pub state_hash: String,
+    pub sequence_id: i64,
+    pub data: ResponseData,
+}
+
+// Bootstrap endpoint for clients without a usable `since_hash`.
+//
+// High‑level:
+// - Returns every row of every tenant table (the `ResponseData` shape).
+// - Reads the chain head and all tables inside one read transaction, so the
+//   snapshot is exactly the state produced by the change_log up to `state_hash`.
+// - Callers with a security profile only get the rows it allows.
+// - `meta.server_time` is the data watermark, as on GET /sync, so it can be passed
+//   straight back as `since`.
+pub async fn bootstrap_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<BootstrapParams>,
+) -> impl IntoResponse {
+    let since = hlc::format(DateTime::UNIX_EPOCH);
+
+    let mut conn = state.db.lock().unwrap();
+
+    let tx = match conn.transaction() {
+        Ok(tx) => tx,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response(),
+    };
+
+    let channel = match pull_channel(&tx, &headers, &params.tenant_id) {
+        Ok(channel) => channel,
+        Err(error) => return error.into_response(),
+    };
+
+    // Read the anchor first; the tables read below can't move past it while the transaction is open.
+    let snapshot = change_log::chain_head(&tx, &params.tenant_id).and_then(|(sequence_id, state_hash)| {
+        let schema_version = schema_version::current(&tx, &params.tenant_id)?;
+        let watermark = fetching::watermark(&tx, &params.tenant_id, &since, data_result::SYNCED_TABLES)?;
+        let mut page = DataPage::unlimited().up_to(&watermark).within(channel.scope);
+        data_result::get_data_result(&tx, &params.tenant_id, &since, &mut page).map(|data| (sequence_id, state_hash, watermark, schema_version, data))
+    });
+
+    // Nothing was written; dropping the transaction simply releases the read lock.
+    drop(tx);
+
+    match snapshot {
+        Ok((sequence_id, state_hash, watermark, schema_version, data)) => {
+            let response = BootstrapResponse {
+                meta: Meta {
+                    server_time: watermark,
+                    since,
+                    schema_version,
+                    has_more: None,
+                    next_cursor: None,
+                },
+                state_hash,
+                sequence_id,
+                data,
+            };
+            (StatusCode::OK, Json(response)).into_response()
+        }
+        Err(e) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "status": "error", "message": e.to_string() }))).into_response()
+        }
+    }
+}
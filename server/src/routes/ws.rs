@@ -0,0 +1,145 @@
+use axum::{
+    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, Query, State},
+    http::HeaderMap,
+    response::IntoResponse,
+};
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::change_log::{self, ChangeLogRecord};
+use crate::compaction;
+use crate::profiles::Channel;
+
+use super::sync::{pull_channel, AppState};
+
+// Query params for GET /ws
+#[derive(Deserialize)]
+pub struct WsParams {
+    pub tenant_id: String,
+    pub since_hash: Option<String>,
+}
+
+// Live delta stream for a tenant.
+//
+// High‑level:
+// - Clients connect with their `tenant_id` and, when reconnecting, the last
+//   `state_hash` they applied as `since_hash`.
+// - We first replay every `change_log` row after that anchor, then forward
+//   each new row the moment `post_sync_handler` commits it.
+// - Every data message is a `ChangeLogRecord` serialized as JSON text, so
+//   clients can verify the hash chain exactly as with `GET /sync/v2`.
+// - Clients with a security profile (X-User-ID) subscribe to its channel and only
+//   receive changes to records it allows, both in the catch-up and live.
+pub async fn ws_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<WsParams>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    // Refuse the upgrade, with a plain HTTP error, to callers that may not pull the tenant.
+    let channel = {
+        let conn = state.db.lock().unwrap();
+        pull_channel(&conn, &headers, &params.tenant_id)
+    };
+    match channel {
+        Ok(channel) => ws.on_upgrade(move |socket| stream_changes(socket, state, params, channel)),
+        Err(error) => error.into_response(),
+    }
+}
+
+async fn stream_changes(mut socket: WebSocket, state: AppState, params: WsParams, channel: Channel) {
+    // Subscribe before reading the catch-up so no change can fall between the two.
+    let mut receiver = state.broadcaster.subscribe(&params.tenant_id, &channel);
+
+    // Resolve where this client is in the chain and collect what it missed.
+    // The DB guard must be dropped before we await on the socket.
+    let catch_up: Result<(i64, Vec<ChangeLogRecord>), serde_json::Value> = {
+        let conn = state.db.lock().unwrap();
+        match &params.since_hash {
+            Some(since_hash) => match change_log::find_sequence_id(&conn, &params.tenant_id, since_hash) {
+                Ok(Some(since_sequence_id)) => changes_after(&conn, &params.tenant_id, &channel, since_sequence_id)
+                    .map_err(|e| json!({ "status": "error", "message": e.to_string() })),
+                Ok(None) => Err(json!({
+                    "error": "bootstrap_required",
+                    "message": "Provided since_hash not found. Client may be too old and must perform a new bootstrap sync.",
+                    "snapshot_id": compaction::latest_snapshot(&conn, &params.tenant_id).ok().flatten().map(|snapshot| snapshot.id),
+                })),
+                Err(e) => Err(json!({ "status": "error", "message": e.to_string() })),
+            },
+            // No anchor: the client is already up to date and only wants live changes.
+            None => change_log::chain_head(&conn, &params.tenant_id)
+                .map(|(head_sequence_id, _)| (head_sequence_id, Vec::new()))
+                .map_err(|e| json!({ "status": "error", "message": e.to_string() })),
+        }
+    };
+
+    let mut last_sequence_id = match catch_up {
+        Ok((last_sequence_id, changes)) => {
+            for change in &changes {
+                if send_change(&mut socket, change).await.is_err() {
+                    return;
+                }
+            }
+            last_sequence_id
+        }
+        Err(error) => {
+            let _ = socket.send(Message::Text(error.to_string())).await;
+            let _ = socket.send(Message::Close(None)).await;
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            received = receiver.recv() => match received {
+                Ok(change) => {
+                    // Skip anything already delivered during catch-up.
+                    if change.sequence_id <= last_sequence_id {
+                        continue;
+                    }
+                    last_sequence_id = change.sequence_id;
+                    if send_change(&mut socket, &change).await.is_err() {
+                        return;
+                    }
+                }
+                // We fell behind the channel; re-read the gap from the change_log.
+                Err(RecvError::Lagged(_)) => {
+                    let missed = {
+                        let conn = state.db.lock().unwrap();
+                        changes_after(&conn, &params.tenant_id, &channel, last_sequence_id)
+                    };
+                    let Ok((scanned_to, missed)) = missed else {
+                        return;
+                    };
+                    last_sequence_id = scanned_to;
+                    for change in missed {
+                        if send_change(&mut socket, &change).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(RecvError::Closed) => return,
+            },
+            incoming = socket.recv() => match incoming {
+                // Pings are answered by axum; clients have nothing else to say on this stream.
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+// The change_log rows after `since_sequence_id` that `channel` may see, and the
+// `sequence_id` of the last row read (seen or not), from which to continue.
+fn changes_after(conn: &Connection, tenant_id: &str, channel: &Channel, since_sequence_id: i64) -> rusqlite::Result<(i64, Vec<ChangeLogRecord>)> {
+    let changes = change_log::fetch_changes_after(conn, tenant_id, since_sequence_id, None)?;
+    let last_sequence_id = changes.last().map_or(since_sequence_id, |last| last.sequence_id);
+    Ok((last_sequence_id, channel.visible(conn, tenant_id, changes)?))
+}
+
+async fn send_change(socket: &mut WebSocket, change: &ChangeLogRecord) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(change).unwrap_or_else(|_| "null".to_string());
+    socket.send(Message::Text(text)).await
+}
@@ -0,0 +1,34 @@
+// This file contains the tenant's schema version.
+//
+// Editing a tenant's metadata (`object_metadata`, `layout_definitions`) changes the shape of
+// its records, so every such change bumps `tenants.schema_version`. Each change_log row
+// carries the version it was accepted under and pulls report the current one in `Meta`:
+// a client that sees a newer version stops applying changes, fetches the new metadata,
+// migrates its local database and resumes with a `since` pull. Pushes from clients that
+// declare an older version are rejected with `schema_upgrade_required`.
+
+use rusqlite::{Connection, OptionalExtension, Result, params};
+
+/// The version of a tenant whose metadata never changed.
+pub const INITIAL_SCHEMA_VERSION: i64 = 1;
+
+// Objects whose changes alter the tenant's schema.
+const METADATA_OBJECTS: &[&str] = &["object_metadata", "layout_definition"];
+
+/// The tenant's current schema version.
+pub fn current(conn: &Connection, tenant_id: &str) -> Result<i64> {
+    let version: Option<i64> = conn
+        .query_row("SELECT schema_version FROM tenants WHERE id = ?1", params![tenant_id], |row| row.get(0))
+        .optional()?;
+    Ok(version.unwrap_or(INITIAL_SCHEMA_VERSION))
+}
+
+/// The schema version a change to `object_name` is accepted under, bumping the tenant's
+/// version first when the change edits metadata. Call it in the savepoint that appends
+/// the change, so a rejected change doesn't leave the bump behind.
+pub fn for_change(conn: &Connection, tenant_id: &str, object_name: &str) -> Result<i64> {
+    if METADATA_OBJECTS.contains(&object_name) {
+        conn.execute("UPDATE tenants SET schema_version = schema_version + 1 WHERE id = ?1", params![tenant_id])?;
+    }
+    current(conn, tenant_id)
+}
@@ -0,0 +1,161 @@
+// This file contains the audit that re-walks a tenant's hash chain.
+
+use std::sync::Mutex;
+
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+
+use crate::change_log;
+use crate::checkpoint;
+
+// Rows are read in pages so very long chains don't have to fit in memory, and so the
+// database is only locked while a page is read, not while it is hashed.
+const VERIFY_PAGE_SIZE: usize = 1000;
+
+/// The outcome of re-walking one tenant's chain from genesis (or its compaction anchor).
+#[derive(Serialize, Debug)]
+pub struct ChainReport {
+    pub tenant_id: String,
+    /// False when a link is broken, or when the latest checkpoint's signature is invalid
+    /// or the chain no longer has the head it vouches for (see `latest_checkpoint`).
+    pub valid: bool,
+    pub rows_checked: usize,
+    /// Where the walk started: `0` / `GENESIS_HASH`, or the latest compaction anchor.
+    pub start_sequence_id: i64,
+    pub start_state_hash: String,
+    /// The last row that verified (the start if none did).
+    pub verified_sequence_id: i64,
+    pub verified_state_hash: String,
+    /// The first row that failed verification; everything after it is unverified.
+    pub first_broken_link: Option<BrokenLink>,
+    /// How the tenant's latest signed checkpoint compares with the re-walked chain.
+    pub latest_checkpoint: Option<CheckpointCheck>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CheckpointCheck {
+    pub sequence_id: i64,
+    pub state_hash: String,
+    /// Whether the signature verifies against the server's own public key.
+    pub signature_valid: bool,
+    /// Whether the verified chain has the checkpoint's `state_hash` at its `sequence_id`.
+    /// `None` when verification stopped at a broken link before reaching it, or when it
+    /// predates the compaction anchor.
+    pub matches_chain: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BrokenLink {
+    pub sequence_id: i64,
+    pub id: String,
+    /// `sequence_gap` when the row links to a row that is no longer in the sequence (rows
+    /// were removed before it); `previous_state_hash_mismatch` when it links to a row
+    /// elsewhere in the sequence (rows were inserted or reordered); `state_hash_mismatch`
+    /// when its stored fields don't hash to its `state_hash` (the row was edited).
+    pub reason: &'static str,
+    pub expected: String,
+    pub found: String,
+    pub hash_version: i64,
+}
+
+/// Recompute every `change_log` row of a tenant in `sequence_id` order and stop at the first
+/// row whose `previous_state_hash` doesn't match the row before it or whose `state_hash`
+/// doesn't match its stored fields.
+///
+/// `db` is locked once per page, so sync traffic carries on during the walk. Changes
+/// appended meanwhile extend the chain and are verified too. A compaction between two
+/// pages can remove rows the walk hasn't reached yet, so every page re-reads the
+/// compaction anchor and the walk restarts from it when it moved past the verified rows.
+/// The latest checkpoint's signature is checked against `trusted_public_key`.
+pub fn verify_chain(db: &Mutex<Connection>, tenant_id: &str, trusted_public_key: &str) -> Result<ChainReport> {
+    let mut report = start_report(&db.lock().unwrap(), tenant_id, trusted_public_key)?;
+
+    loop {
+        let page = {
+            let conn = db.lock().unwrap();
+            let (anchor_sequence_id, _) = change_log::chain_start(&conn, tenant_id)?;
+            if anchor_sequence_id > report.verified_sequence_id {
+                report = start_report(&conn, tenant_id, trusted_public_key)?;
+            }
+            change_log::fetch_changes_after(&conn, tenant_id, report.verified_sequence_id, Some(VERIFY_PAGE_SIZE))?
+        };
+        let page_len = page.len();
+        for change in page {
+            report.rows_checked += 1;
+
+            let broken = if change.previous_state_hash != report.verified_state_hash {
+                // Rows are taken in `sequence_id` order, so a row that doesn't follow its predecessor
+                // either links to a row that is gone (a gap in the sequence) or to one elsewhere in it.
+                let predecessor = change_log::find_sequence_id(&db.lock().unwrap(), tenant_id, &change.previous_state_hash)?;
+                let reason = if predecessor.is_some() { "previous_state_hash_mismatch" } else { "sequence_gap" };
+                Some((reason, report.verified_state_hash.clone(), change.previous_state_hash.clone()))
+            } else {
+                let recomputed = change.recompute_state_hash();
+                (recomputed != change.state_hash).then(|| ("state_hash_mismatch", recomputed, change.state_hash.clone()))
+            };
+
+            if let Some((reason, expected, found)) = broken {
+                report.first_broken_link = Some(BrokenLink {
+                    sequence_id: change.sequence_id,
+                    id: change.id,
+                    reason,
+                    expected,
+                    found,
+                    hash_version: change.hash_version.as_i64(),
+                });
+                break;
+            }
+
+            if let Some(check) = report.latest_checkpoint.as_mut().filter(|check| check.sequence_id == change.sequence_id) {
+                check.matches_chain = Some(check.state_hash == change.state_hash);
+            }
+            report.verified_sequence_id = change.sequence_id;
+            report.verified_state_hash = change.state_hash;
+        }
+        if report.first_broken_link.is_some() {
+            break;
+        }
+        if page_len < VERIFY_PAGE_SIZE {
+            // The whole chain verified without passing the checkpoint's row: it was cut back.
+            if let Some(check) = report
+                .latest_checkpoint
+                .as_mut()
+                .filter(|check| check.matches_chain.is_none() && check.sequence_id > report.start_sequence_id)
+            {
+                check.matches_chain = Some(false);
+            }
+            break;
+        }
+    }
+
+    // A forged checkpoint, or a chain cut back past a signed head, is tampering as much as a broken link.
+    let checkpoint_failed = report.latest_checkpoint.as_ref().is_some_and(|check| !check.signature_valid || check.matches_chain == Some(false));
+    report.valid = report.first_broken_link.is_none() && !checkpoint_failed;
+    Ok(report)
+}
+
+// A report of nothing verified yet, starting at the tenant's compaction anchor (or genesis).
+fn start_report(conn: &Connection, tenant_id: &str, trusted_public_key: &str) -> Result<ChainReport> {
+    let (start_sequence_id, start_state_hash) = change_log::chain_start(conn, tenant_id)?;
+    let mut report = ChainReport {
+        tenant_id: tenant_id.to_string(),
+        valid: true,
+        rows_checked: 0,
+        start_sequence_id,
+        start_state_hash: start_state_hash.clone(),
+        verified_sequence_id: start_sequence_id,
+        verified_state_hash: start_state_hash,
+        first_broken_link: None,
+        latest_checkpoint: checkpoint::latest_checkpoint(conn, tenant_id, None)?.map(|checkpoint| CheckpointCheck {
+            signature_valid: checkpoint.verify(trusted_public_key),
+            sequence_id: checkpoint.sequence_id,
+            state_hash: checkpoint.state_hash,
+            matches_chain: None,
+        }),
+    };
+    // A checkpoint at or before the compaction anchor can only be compared with the anchor itself.
+    if let Some(check) = report.latest_checkpoint.as_mut().filter(|check| check.sequence_id <= start_sequence_id) {
+        check.matches_chain = (check.sequence_id == start_sequence_id).then(|| check.state_hash == report.start_state_hash);
+    }
+    Ok(report)
+}
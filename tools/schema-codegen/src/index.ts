@@ -31,6 +31,8 @@ interface ResolvedProperty {
   isOptional: boolean;
   referenceTarget?: string;
   isSqlJson?: boolean;
+  omitNull?: boolean;
+  isUnique?: boolean;
 }
 
 interface ResolvedInterface {
@@ -44,10 +46,13 @@ interface ResolvedInterface {
   };
 }
 
-// Extract per-property JSDoc tags like `@reference TargetRecord` and `@sqlJson`
-function parsePropertyTags(prop: PropertySignature): { referenceTarget?: string; isSqlJson: boolean } {
+// Extract per-property JSDoc tags like `@reference TargetRecord`, `@sqlJson`, `@omitNull`
+// (an optional field left out of the JSON when unset instead of sent as null) and `@unique`
+function parsePropertyTags(prop: PropertySignature): { referenceTarget?: string; isSqlJson: boolean; omitNull: boolean; isUnique: boolean } {
   let referenceTarget: string | undefined;
   let isSqlJson = false;
+  let omitNull = false;
+  let isUnique = false;
 
   const tags = prop.getJsDocs().flatMap(d => d.getTags());
   for (const t of tags) {
@@ -58,10 +63,14 @@ function parsePropertyTags(prop: PropertySignature): { referenceTarget?: string;
       referenceTarget = (comment.split(/\s+/)[0] || '').trim() || undefined;
     } else if (raw === 'sqlJson' || raw.toLowerCase() === 'sqljson') {
       isSqlJson = true;
+    } else if (raw === 'omitNull') {
+      omitNull = true;
+    } else if (raw === 'unique') {
+      isUnique = true;
     }
   }
 
-  return { referenceTarget, isSqlJson };
+  return { referenceTarget, isSqlJson, omitNull, isUnique };
 }
 
 // --- Phase 1: Resolution Logic ---
@@ -81,13 +90,15 @@ function resolveInterfaces(sourceFile: SourceFile): ResolvedInterface[] {
         if (existingIndex !== -1) {
           properties.splice(existingIndex, 1);
         }
-        const { referenceTarget, isSqlJson } = parsePropertyTags(prop);
+        const { referenceTarget, isSqlJson, omitNull, isUnique } = parsePropertyTags(prop);
         properties.push({
           name: prop.getName(),
           type: prop.getType(), // KEY CHANGE: Store the rich Type object
           isOptional: prop.hasQuestionToken(),
           referenceTarget,
           isSqlJson,
+          omitNull,
+          isUnique,
         });
       });
     };
@@ -168,12 +179,20 @@ function generateSqlSchema(resolvedInterfaces: ResolvedInterface[]): string {
         columnDefinition += ' NOT NULL';
       }
 
+      if (prop.isUnique) {
+        columnDefinition += ' UNIQUE';
+      }
+
       columnDefinition += constraints;
 
       if (!propType.isStringLiteral()) {
           if (prop.name === 'version' && !prop.isOptional) {
             columnDefinition += ' DEFAULT 0';
           }
+          // Tenants start at schema version 1 (see `TenantRecord.schema_version`).
+          if (prop.name === 'schema_version' && !prop.isOptional) {
+            columnDefinition += ' DEFAULT 1';
+          }
           if (prop.name === 'created_at' && !prop.isOptional) {
             columnDefinition += ' DEFAULT (CURRENT_TIMESTAMP)';
           }
@@ -827,7 +846,7 @@ function generateRustDataResultFile(resolvedInterfaces: ResolvedInterface[]): st
 
 use rusqlite::{Connection, Result};
 use crate::models::*;
-use crate::fetching::fetch_all;
+use crate::fetching::{fetch_all, DataPage, SyncedTable};
 `;
 
     const fetchFields = responseData.properties.map(p => {
@@ -835,16 +854,32 @@ use crate::fetching::fetch_all;
         const tableName = fieldName;
         const recordType = getTypeName(p.type.getArrayElementTypeOrThrow());
         const decoderFn = `crate::models::${toSnake(recordType)}_from_row`;
-        return `        ${fieldName}: fetch_all(&conn, "${tableName}", tenant_id, since, ${decoderFn})?,`;
+        return `        ${fieldName}: fetch_all(conn, "${tableName}", tenant_id, since, page, ${decoderFn})?,`;
     });
 
-    const functionBody = `pub fn get_data_result(conn: &Connection, tenant_id: &str, since: &str) -> Result<ResponseData> {
+    const functionBody = `pub fn get_data_result(conn: &Connection, tenant_id: &str, since: &str, page: &mut DataPage) -> Result<ResponseData> {
     Ok(ResponseData {
 ${fetchFields.join('\n')}
     })
 }`;
 
-    return [header, functionBody].join('\n\n');
+    // One entry per ResponseData table, keyed by the overlay/change_log `object_name`.
+    const syncedTables = responseData.properties.map(p => {
+        const recordType = getTypeName(p.type.getArrayElementTypeOrThrow());
+        const recordInterface = resolvedInterfaces.find(i => i.name === recordType);
+        const hasColumn = (name: string) => !!recordInterface?.properties.some(rp => rp.name === name);
+        const objectName = toSnake(recordType.replace(/Record$/, ''));
+        const dataProp = recordInterface?.properties.find(rp => rp.name === 'data');
+        const dataType = dataProp ? getTypeName(dataProp.type) : 'serde_json::Value';
+        const validateData = `|data| serde_json::from_str::<${dataType}>(data).map(|_| ())`;
+        return `    SyncedTable { object_name: "${objectName}", table_name: "${p.name}", has_status: ${hasColumn('status')}, has_object_type: ${hasColumn('object_type')}, validate_data: ${validateData} },`;
+    });
+
+    const syncedTablesConst = `pub const SYNCED_TABLES: &[SyncedTable] = &[
+${syncedTables.join('\n')}
+];`;
+
+    return [header, functionBody, syncedTablesConst].join('\n\n');
  
 }
 
@@ -885,14 +920,20 @@ use rusqlite::Row;
     for (const intf of interfacesToGenerate) {
         const properties = intf.properties.map(p => {
             let typeString = mapTsTypeToRust(p.type);
+            // `version` is an INTEGER column used for optimistic concurrency checks;
+            // `schema_version` is an INTEGER counter too.
+            if ((p.name === 'version' || p.name === 'schema_version') && p.type.isNumber()) {
+                typeString = 'i64';
+            }
             if (p.isOptional && !typeString.startsWith('Option<')) {
                 typeString = `Option<${typeString}>`;
             }
             
             const fieldName = p.name === 'type' ? 'r#type' : p.name;
             const serdeRename = p.name === 'type' ? `#[serde(rename = "type")]\n    ` : '';
+            const serdeSkip = p.omitNull ? `#[serde(skip_serializing_if = "Option::is_none")]\n    ` : '';
 
-            return `    ${serdeRename}pub ${fieldName}: ${typeString},`;
+            return `    ${serdeRename}${serdeSkip}pub ${fieldName}: ${typeString},`;
         });
 
         const derive = intf.name === 'ResponseData'
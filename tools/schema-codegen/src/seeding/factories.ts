@@ -42,6 +42,7 @@ export function tenant(args: { id?: string; data?: Partial<TenantData> } = {}):
       ...args.data
     },
     version: 0,
+    schema_version: 1,
     created_at: now(),
     updated_at: now()
   };
@@ -44,13 +44,14 @@ function insertBaseRecord(db: Database.Database, tableName: string, record: AnyR
 }
 
 function insertTenant(db: Database.Database, tenant: TenantRecord) {
-  const sql = `INSERT INTO tenants (id, data, version, created_by, modified_by, created_at, updated_at)
-               VALUES (?, ?, ?, ?, ?, ?, ?)`;
+  const sql = `INSERT INTO tenants (id, data, version, schema_version, created_by, modified_by, created_at, updated_at)
+               VALUES (?, ?, ?, ?, ?, ?, ?, ?)`;
 
   db.prepare(sql).run(
     tenant.id,
     JSON.stringify(tenant.data),
     tenant.version,
+    tenant.schema_version,
     tenant.created_by || null,
     tenant.modified_by || null,
     tenant.created_at,